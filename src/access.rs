@@ -0,0 +1,235 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::IpAddr,
+    str::FromStr,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use config::ConfigError;
+use lazy_static::lazy_static;
+
+use crate::{
+    settings::{Access, Settings},
+    SETTINGS,
+};
+
+lazy_static! {
+    static ref LIMITS: RwLock<Limits> = RwLock::new(Limits::from(&SETTINGS.access));
+    static ref TRACKER: Mutex<Tracker> = Mutex::new(Tracker::default());
+}
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+const MAX_TRACKED_ADDRESSES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, normalize(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+
+        let network = normalize(
+            address
+                .trim()
+                .parse::<IpAddr>()
+                .map_err(|e| format!("{}: {}", s, e))?,
+        );
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .map_err(|e| format!("{}: {}", s, e))?,
+            None => max_prefix,
+        };
+
+        if prefix > max_prefix {
+            return Err(format!("{}: prefix is longer than the address", s));
+        }
+
+        Ok(Self { network, prefix })
+    }
+}
+
+// Dual stack listeners report IPv4 peers as IPv4-mapped IPv6 addresses
+fn normalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+pub fn parse_list(entries: &[String]) -> Vec<Cidr> {
+    entries
+        .iter()
+        .filter_map(|entry| match entry.parse() {
+            Ok(cidr) => Some(cidr),
+            Err(e) => {
+                warn!("Ignoring invalid address {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    Denied,
+    NotAllowed,
+    TooManySessions,
+    TooManyConnections,
+    ConnectingTooFast,
+    Banned { permanent: bool },
+}
+
+impl Rejection {
+    // Address rules won't change on a retry, capacity limits might
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Self::Denied | Self::NotAllowed => true,
+            Self::Banned { permanent } => *permanent,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Denied => write!(f, "address is denied"),
+            Self::NotAllowed => write!(f, "address is not allowed"),
+            Self::TooManySessions => write!(f, "too many sessions"),
+            Self::TooManyConnections => write!(f, "too many connections from address"),
+            Self::ConnectingTooFast => write!(f, "connecting too fast"),
+            Self::Banned { .. } => write!(f, "address is banned"),
+        }
+    }
+}
+
+struct Limits {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+    max_sessions: usize,
+    max_connections_per_ip: usize,
+    connections_per_minute: usize,
+}
+
+impl From<&Access> for Limits {
+    fn from(access: &Access) -> Self {
+        Self {
+            allow: parse_list(&access.allow),
+            deny: parse_list(&access.deny),
+            max_sessions: access.max_sessions,
+            max_connections_per_ip: access.max_connections_per_ip,
+            connections_per_minute: access.connections_per_minute,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Tracker {
+    sessions: usize,
+    connections: HashMap<IpAddr, usize>,
+    attempts: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+// Held by a session for as long as it is connected
+#[derive(Debug)]
+pub struct Permit {
+    ip: IpAddr,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut tracker = TRACKER.lock().unwrap();
+        tracker.sessions = tracker.sessions.saturating_sub(1);
+        if let Some(count) = tracker.connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                tracker.connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+pub fn admit(ip: IpAddr) -> Result<Permit, Rejection> {
+    let ip = normalize(ip);
+    let limits = LIMITS.read().unwrap();
+    let mut tracker = TRACKER.lock().unwrap();
+
+    let now = Instant::now();
+    if tracker.attempts.len() > MAX_TRACKED_ADDRESSES {
+        // Forget addresses that haven't connected in a while so the map doesn't grow forever
+        tracker.attempts.retain(|_, attempts| {
+            matches!(attempts.back(), Some(at) if now.duration_since(*at) <= RATE_WINDOW)
+        });
+    }
+
+    let attempts = tracker.attempts.entry(ip).or_default();
+    while matches!(attempts.front(), Some(at) if now.duration_since(*at) > RATE_WINDOW) {
+        attempts.pop_front();
+    }
+    attempts.push_back(now);
+    let recent_attempts = attempts.len();
+
+    if limits.deny.iter().any(|cidr| cidr.contains(ip)) {
+        return Err(Rejection::Denied);
+    }
+
+    if !limits.allow.is_empty() && !limits.allow.iter().any(|cidr| cidr.contains(ip)) {
+        return Err(Rejection::NotAllowed);
+    }
+
+    if limits.connections_per_minute > 0 && recent_attempts > limits.connections_per_minute {
+        return Err(Rejection::ConnectingTooFast);
+    }
+
+    if limits.max_sessions > 0 && tracker.sessions >= limits.max_sessions {
+        return Err(Rejection::TooManySessions);
+    }
+
+    let connections = tracker.connections.get(&ip).copied().unwrap_or_default();
+    if limits.max_connections_per_ip > 0 && connections >= limits.max_connections_per_ip {
+        return Err(Rejection::TooManyConnections);
+    }
+
+    tracker.sessions += 1;
+    *tracker.connections.entry(ip).or_default() += 1;
+
+    Ok(Permit { ip })
+}
+
+pub fn reload() -> Result<(), ConfigError> {
+    let settings = Settings::new()?;
+    *LIMITS.write().unwrap() = Limits::from(&settings.access);
+    Ok(())
+}
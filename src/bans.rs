@@ -0,0 +1,239 @@
+use std::{fmt, fs, io, net::IpAddr, sync::RwLock};
+
+use chrono::{DateTime, Duration, Local};
+use lazy_static::lazy_static;
+
+use crate::{access::Cidr, SETTINGS};
+
+// Bans the proxy enforces itself, so players can be kept out without touching server data.
+// Each kind is checked as soon as the proxy knows it: addresses when the connection is
+// accepted, HDIDs at Init_Init, accounts at Login_Request and characters at Welcome_Request.
+// Bans are kept in a JSON file and managed from the monitor
+
+lazy_static! {
+    static ref BANS: RwLock<Bans> = RwLock::new(Bans::load());
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum Target {
+    // An address or CIDR range
+    Ip(String),
+    Hdid(String),
+    Account(String),
+    Character(String),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(ip) => write!(f, "ip {}", ip),
+            Self::Hdid(hdid) => write!(f, "hdid {}", hdid),
+            Self::Account(account) => write!(f, "account {}", account),
+            Self::Character(character) => write!(f, "character {}", character),
+        }
+    }
+}
+
+// How a banned player is turned away
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    // The Init ban reply, permanent unless the ban expires. Clients past Init can't act on it,
+    // so account and character bans get a login rejection instead
+    #[default]
+    Init,
+    // A login rejection. Bans found before the client logs in wait for it to try
+    Login,
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub id: u64,
+    pub target: Target,
+    #[serde(default)]
+    pub reply: Reply,
+    #[serde(default)]
+    pub reason: Option<String>,
+    pub created: DateTime<Local>,
+    #[serde(default)]
+    pub expires: Option<DateTime<Local>>,
+}
+
+impl Ban {
+    pub fn is_permanent(&self) -> bool {
+        self.expires.is_none()
+    }
+
+    fn expired(&self, now: DateTime<Local>) -> bool {
+        matches!(self.expires, Some(expires) if expires <= now)
+    }
+}
+
+impl fmt::Display for Ban {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is banned", self.target)?;
+        if let Some(expires) = self.expires {
+            write!(f, " until {}", expires.format("%Y-%m-%d %H:%M"))?;
+        }
+        if let Some(reason) = &self.reason {
+            write!(f, " ({})", reason)?;
+        }
+        Ok(())
+    }
+}
+
+// A ban as the monitor asks for it
+#[derive(Debug, Deserialize)]
+pub struct NewBan {
+    pub target: Target,
+    #[serde(default)]
+    pub reply: Reply,
+    #[serde(default)]
+    pub reason: Option<String>,
+    // Permanent when not given
+    #[serde(default)]
+    pub minutes: Option<i64>,
+}
+
+#[derive(Default)]
+struct Bans {
+    bans: Vec<Ban>,
+    next_id: u64,
+}
+
+impl Bans {
+    fn load() -> Self {
+        let path = &SETTINGS.bans.file;
+        let bans: Vec<Ban> = match fs::read_to_string(path) {
+            Ok(json) => match serde_json::from_str(&json) {
+                Ok(bans) => bans,
+                Err(e) => {
+                    error!("Ignoring invalid bans file {}: {}", path, e);
+                    Vec::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                error!("Unable to read bans file {}: {}", path, e);
+                Vec::new()
+            }
+        };
+
+        let next_id = bans.iter().map(|ban| ban.id).max().unwrap_or_default() + 1;
+        Self { bans, next_id }
+    }
+
+    fn find(&self, matches: impl Fn(&Target) -> bool) -> Option<Ban> {
+        let now = Local::now();
+        self.bans
+            .iter()
+            .find(|ban| !ban.expired(now) && matches(&ban.target))
+            .cloned()
+    }
+}
+
+// Expired bans are dropped whenever the file is written
+fn save(bans: &[Ban]) -> io::Result<()> {
+    let now = Local::now();
+    let bans: Vec<&Ban> = bans.iter().filter(|ban| !ban.expired(now)).collect();
+    fs::write(&SETTINGS.bans.file, serde_json::to_vec_pretty(&bans)?)
+}
+
+pub fn check_ip(ip: IpAddr) -> Option<Ban> {
+    BANS.read().unwrap().find(|target| match target {
+        Target::Ip(range) => matches!(range.parse::<Cidr>(), Ok(cidr) if cidr.contains(ip)),
+        _ => false,
+    })
+}
+
+pub fn check_hdid(hdid: &str) -> Option<Ban> {
+    BANS.read()
+        .unwrap()
+        .find(|target| matches!(target, Target::Hdid(banned) if banned == hdid))
+}
+
+pub fn check_account(account: &str) -> Option<Ban> {
+    BANS.read().unwrap().find(
+        |target| matches!(target, Target::Account(banned) if banned.eq_ignore_ascii_case(account)),
+    )
+}
+
+pub fn check_character(character: &str) -> Option<Ban> {
+    BANS.read().unwrap().find(|target| {
+        matches!(target, Target::Character(banned) if banned.eq_ignore_ascii_case(character))
+    })
+}
+
+pub fn list() -> Vec<Ban> {
+    let now = Local::now();
+    BANS.read()
+        .unwrap()
+        .bans
+        .iter()
+        .filter(|ban| !ban.expired(now))
+        .cloned()
+        .collect()
+}
+
+pub fn add(new: NewBan) -> Result<Ban, String> {
+    if let Target::Ip(range) = &new.target {
+        range.parse::<Cidr>()?;
+    }
+
+    let now = Local::now();
+    let expires = match new.minutes {
+        Some(minutes) if minutes <= 0 => {
+            return Err(format!(
+                "Ban length must be positive, not {} minutes",
+                minutes
+            ))
+        }
+        Some(minutes) => Some(
+            Duration::try_minutes(minutes)
+                .and_then(|length| now.checked_add_signed(length))
+                .ok_or_else(|| format!("Ban length of {} minutes is too long", minutes))?,
+        ),
+        None => None,
+    };
+
+    let mut bans = BANS.write().unwrap();
+    let ban = Ban {
+        id: bans.next_id,
+        target: new.target,
+        reply: new.reply,
+        reason: new.reason,
+        created: now,
+        expires,
+    };
+
+    // Only take the ban on once it's safely written
+    let mut updated = bans.bans.clone();
+    updated.push(ban.clone());
+    save(&updated).map_err(|e| format!("Unable to save bans: {}", e))?;
+    bans.bans = updated;
+    bans.next_id += 1;
+
+    info!("Added ban {}: {}", ban.id, ban);
+    Ok(ban)
+}
+
+pub fn remove(id: u64) -> Result<(), String> {
+    let mut bans = BANS.write().unwrap();
+    if !bans.bans.iter().any(|ban| ban.id == id) {
+        return Err(format!("No ban {}", id));
+    }
+
+    let updated: Vec<Ban> = bans
+        .bans
+        .iter()
+        .filter(|ban| ban.id != id)
+        .cloned()
+        .collect();
+    save(&updated).map_err(|e| format!("Unable to save bans: {}", e))?;
+    bans.bans = updated;
+
+    info!("Removed ban {}", id);
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use clap::Parser;
+use eoproxy::mock::{self, MockCharacter, MockConfig};
+use tokio::net::TcpListener;
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "A mock endless online server for testing eoproxy offline"
+)]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    #[arg(long, default_value_t = 8078)]
+    port: u16,
+
+    #[arg(long, default_value_t = 6)]
+    encode_multiple: u8,
+
+    #[arg(long, default_value_t = 10)]
+    decode_multiple: u8,
+
+    #[arg(long, default_value_t = 1)]
+    player_id: u16,
+
+    /// Name of the character offered at login
+    #[arg(long, default_value = "mock")]
+    character: String,
+
+    /// Send packets the mock has no reply for back to the client
+    #[arg(long)]
+    echo: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    tracing_subscriber::fmt::init();
+
+    let config = MockConfig {
+        encode_multiple: args.encode_multiple,
+        decode_multiple: args.decode_multiple,
+        player_id: args.player_id,
+        characters: vec![MockCharacter {
+            id: 1,
+            name: args.character,
+            level: 1,
+        }],
+        echo: args.echo,
+        ..Default::default()
+    };
+
+    let listener = TcpListener::bind((args.host.as_str(), args.port)).await?;
+    println!("mock server listening at {}", listener.local_addr()?);
+    mock::serve(listener, config).await?;
+    Ok(())
+}
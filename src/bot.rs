@@ -0,0 +1,391 @@
+use std::{io, path::Path, time::Duration};
+
+use chrono::{Local, Timelike};
+use config::{Config, File};
+use eo::{
+    data::{EOByte, EOInt, EOShort, EOThree, Serializeable, StreamReader},
+    net::ClientSequencer,
+    protocol::{
+        client,
+        server::{self, init::InitData},
+        Coords, Direction, PacketAction, PacketFamily,
+    },
+};
+use tokio::{net::TcpStream, time::timeout};
+
+use crate::{bus::Bus, cli::BotArgs, serialize, versions, PacketBuf};
+
+// A headless game client. Bot::connect does the Init handshake, then login, select_character
+// and enter_game get a character in game for a Scenario to drive
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "do", rename_all = "snake_case")]
+pub enum Step {
+    Walk { direction: String },
+    Say { message: String },
+    PickUp { item: EOShort },
+    Wait { ms: u64 },
+    Logout,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    // Where the character starts, so walks can be sent with the right coordinates
+    #[serde(default)]
+    pub x: EOByte,
+    #[serde(default)]
+    pub y: EOByte,
+    // Pause after each step, like a player would
+    #[serde(default)]
+    pub think_ms: u64,
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    // Scenario files can be in any format the settings can: toml, json or ron
+    pub fn load(path: &Path) -> Result<Self, config::ConfigError> {
+        Config::builder()
+            .add_source(File::from(path))
+            .build()?
+            .try_deserialize()
+    }
+}
+
+fn error(message: String) -> io::Error {
+    io::Error::other(message)
+}
+
+fn direction(name: &str) -> io::Result<Direction> {
+    match name.to_lowercase().as_str() {
+        "down" => Ok(Direction::Down),
+        "left" => Ok(Direction::Left),
+        "up" => Ok(Direction::Up),
+        "right" => Ok(Direction::Right),
+        _ => Err(error(format!("Unknown direction {}", name))),
+    }
+}
+
+// Hundredths of a second into the day, as the client sends with walks
+fn timestamp() -> EOThree {
+    let now = Local::now();
+    now.hour() * 360000 + now.minute() * 6000 + now.second() * 100 + now.nanosecond() / 10000000
+}
+
+pub struct Bot {
+    bus: Bus,
+    sequencer: ClientSequencer,
+    pub player_id: EOShort,
+    pub session_id: EOShort,
+    pub character_id: EOInt,
+    pub coords: Coords,
+    // Scenario steps taken so far
+    pub steps: u64,
+}
+
+impl Bot {
+    // version is the client version to claim, as major.minor.patch
+    pub async fn connect(addr: &str, version: &str) -> io::Result<Self> {
+        let version = versions::parse(version)
+            .ok_or_else(|| error(format!("Bad client version {:?}", version)))?;
+
+        let socket = timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
+            .await
+            .map_err(|_| error(format!("Timed out connecting to {}", addr)))??;
+        let mut bot = Self {
+            bus: Bus::new(socket, "Bot".to_string()),
+            sequencer: ClientSequencer::default(),
+            player_id: 0,
+            session_id: 0,
+            character_id: 0,
+            coords: Coords::new(),
+            steps: 0,
+        };
+
+        let mut init = client::init::Init::new();
+        init.challenge = rand::random::<EOThree>() % 11092110;
+        init.version = version;
+        init.hdid = format!("{}", rand::random::<u32>());
+        bot.bus
+            .send(PacketAction::Init, PacketFamily::Init, serialize(&init))
+            .await?;
+
+        let reply = bot.expect(PacketFamily::Init, PacketAction::Init).await?;
+        let mut init = server::init::Init::new();
+        init.deserialize(&StreamReader::new(&reply));
+        let ok = match init.data {
+            InitData::Ok(ok) => ok,
+            data => return Err(error(format!("Init failed: {:?}", data))),
+        };
+
+        bot.player_id = ok.player_id;
+        bot.sequencer
+            .set_init_sequence(ok.seq_bytes[0].into(), ok.seq_bytes[1].into());
+        bot.bus
+            .packet_processor
+            .set_multiples(ok.encode_multiple, ok.decode_multiple);
+
+        let mut accept = client::connection::Accept::new();
+        accept.encode_multiple = ok.encode_multiple.into();
+        accept.decode_multiple = ok.decode_multiple.into();
+        accept.player_id = ok.player_id;
+        bot.send(
+            PacketAction::Accept,
+            PacketFamily::Connection,
+            serialize(&accept),
+        )
+        .await?;
+
+        Ok(bot)
+    }
+
+    pub async fn send(
+        &mut self,
+        action: PacketAction,
+        family: PacketFamily,
+        payload: PacketBuf,
+    ) -> io::Result<()> {
+        let mut buf = vec![self.sequencer.get_sequence_bytes()[0]];
+        buf.extend(payload);
+        self.bus.send(action, family, buf).await
+    }
+
+    // Waits for a packet, answering pings in the meantime. Returns its payload
+    pub async fn expect(
+        &mut self,
+        family: PacketFamily,
+        action: PacketAction,
+    ) -> io::Result<PacketBuf> {
+        timeout(REPLY_TIMEOUT, async {
+            loop {
+                let packet = match self.bus.recv().await {
+                    Some(Ok(packet)) if packet.len() >= 2 => packet,
+                    Some(Ok(_)) | None => continue,
+                    Some(Err(e)) => return Err(e),
+                };
+
+                if packet[0] == action.to_byte() && packet[1] == family.to_byte() {
+                    return Ok(packet[2..].to_vec());
+                }
+                self.handle(&packet).await?;
+            }
+        })
+        .await
+        .map_err(|_| error(format!("No {:?}_{:?} from the server", family, action)))?
+    }
+
+    async fn handle(&mut self, packet: &[u8]) -> io::Result<()> {
+        if packet[0] == PacketAction::Player.to_byte()
+            && packet[1] == PacketFamily::Connection.to_byte()
+        {
+            let mut ping = server::connection::Player::new();
+            ping.deserialize(&StreamReader::new(&packet[2..]));
+            self.sequencer
+                .set_new_initial_sequence_number(ping.seq1.into(), ping.seq2.into());
+
+            let pong = client::connection::Ping::new();
+            self.send(
+                PacketAction::Ping,
+                PacketFamily::Connection,
+                serialize(&pong),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    // Logs in and returns the names and ids of the account's characters
+    pub async fn login(
+        &mut self,
+        username: &str,
+        password: &str,
+    ) -> io::Result<Vec<(String, EOInt)>> {
+        let mut request = client::login::Request::new();
+        request.username = username.to_string();
+        request.password = password.to_string();
+        self.send(
+            PacketAction::Request,
+            PacketFamily::Login,
+            serialize(&request),
+        )
+        .await?;
+
+        let reply = self
+            .expect(PacketFamily::Login, PacketAction::Reply)
+            .await?;
+        let mut login = server::login::Reply::new();
+        login.deserialize(&StreamReader::new(&reply));
+        match login.data {
+            server::login::ReplyData::Ok(ok) => Ok(ok
+                .character_list
+                .characters
+                .into_iter()
+                .map(|character| (character.name, character.id))
+                .collect()),
+            _ => Err(error(format!("Login failed: {:?}", login.reply_code))),
+        }
+    }
+
+    pub async fn select_character(&mut self, character_id: EOInt) -> io::Result<()> {
+        let mut request = client::welcome::Request::new();
+        request.character_id = character_id;
+        self.send(
+            PacketAction::Request,
+            PacketFamily::Welcome,
+            serialize(&request),
+        )
+        .await?;
+
+        let reply = self
+            .expect(PacketFamily::Welcome, PacketAction::Reply)
+            .await?;
+        let mut welcome = server::welcome::Reply::new();
+        welcome.deserialize(&StreamReader::new(&reply));
+        match welcome.data {
+            server::welcome::ReplyData::SelectCharacter(select) => {
+                self.session_id = select.session_id;
+                self.character_id = select.character_id;
+                Ok(())
+            }
+            _ => Err(error(format!(
+                "Select character failed: {:?}",
+                welcome.reply_code
+            ))),
+        }
+    }
+
+    pub async fn enter_game(&mut self) -> io::Result<()> {
+        let mut request = client::welcome::Msg::new();
+        request.session_id = self.session_id.into();
+        request.character_id = self.character_id;
+        self.send(
+            PacketAction::Msg,
+            PacketFamily::Welcome,
+            serialize(&request),
+        )
+        .await?;
+
+        self.expect(PacketFamily::Welcome, PacketAction::Reply)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn walk(&mut self, direction: Direction) -> io::Result<()> {
+        match direction {
+            Direction::Down => self.coords.y = self.coords.y.saturating_add(1),
+            Direction::Left => self.coords.x = self.coords.x.saturating_sub(1),
+            Direction::Up => self.coords.y = self.coords.y.saturating_sub(1),
+            Direction::Right => self.coords.x = self.coords.x.saturating_add(1),
+        }
+
+        let mut walk = client::walk::Player::new();
+        walk.walk.direction = direction;
+        walk.walk.timestamp = timestamp();
+        walk.walk.coords = self.coords;
+        self.send(PacketAction::Player, PacketFamily::Walk, serialize(&walk))
+            .await
+    }
+
+    pub async fn say(&mut self, message: &str) -> io::Result<()> {
+        let mut report = client::talk::Report::new();
+        report.message = message.to_string();
+        self.send(PacketAction::Report, PacketFamily::Talk, serialize(&report))
+            .await
+    }
+
+    pub async fn pick_up(&mut self, item_index: EOShort) -> io::Result<()> {
+        let mut get = client::item::Get::new();
+        get.take_item_index = item_index;
+        self.send(PacketAction::Get, PacketFamily::Item, serialize(&get))
+            .await
+    }
+
+    // Packets that arrive between steps are read and answered where needed
+    pub async fn idle(&mut self, duration: Duration) -> io::Result<()> {
+        let idled = timeout(duration, async {
+            loop {
+                match self.bus.recv().await {
+                    Some(Ok(packet)) if packet.len() >= 2 => self.handle(&packet).await?,
+                    Some(Ok(_)) | None => {}
+                    Some(Err(e)) => return Err(e),
+                }
+            }
+        })
+        .await;
+
+        match idled {
+            Ok(result) => result,
+            // Idling the whole time is what was wanted
+            Err(_) => Ok(()),
+        }
+    }
+
+    // Returns false once the scenario should stop
+    pub async fn step(&mut self, step: &Step) -> io::Result<bool> {
+        debug!("Bot step {:?}", step);
+        match step {
+            Step::Walk { direction: name } => self.walk(direction(name)?).await?,
+            Step::Say { message } => self.say(message).await?,
+            Step::PickUp { item } => self.pick_up(*item).await?,
+            Step::Wait { ms } => self.idle(Duration::from_millis(*ms)).await?,
+            Step::Logout => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    pub async fn run(&mut self, scenario: &Scenario) -> io::Result<()> {
+        self.coords.x = scenario.x;
+        self.coords.y = scenario.y;
+
+        for step in &scenario.steps {
+            if !self.step(step).await? {
+                break;
+            }
+            self.steps += 1;
+            self.idle(Duration::from_millis(scenario.think_ms)).await?;
+        }
+
+        Ok(())
+    }
+}
+
+// Connects, gets a character in game and plays the scenario through
+pub async fn play(
+    addr: &str,
+    version: &str,
+    username: &str,
+    password: &str,
+    character: Option<&str>,
+    scenario: &Scenario,
+) -> io::Result<()> {
+    let mut bot = Bot::connect(addr, version).await?;
+
+    let characters = bot.login(username, password).await?;
+    let (_, character_id) = characters
+        .iter()
+        .find(|(name, _)| character.is_none_or(|wanted| name.eq_ignore_ascii_case(wanted)))
+        .ok_or_else(|| error(format!("{} has no character to play", username)))?;
+
+    bot.select_character(*character_id).await?;
+    bot.enter_game().await?;
+    bot.run(scenario).await
+}
+
+pub async fn run(args: &BotArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let scenario = Scenario::load(&args.scenario)?;
+
+    play(
+        &args.server,
+        &args.version,
+        &args.username,
+        &args.password,
+        args.character.as_deref(),
+        &scenario,
+    )
+    .await?;
+
+    println!("Finished {:?}", args.scenario);
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+
+use crate::filter::{Filter, PacketContext};
+
+// Breakpoints apply to every session, but belong to the monitor that set them so they don't
+// outlive it
+
+lazy_static! {
+    static ref BREAKPOINT: RwLock<Option<(u64, Filter)>> = RwLock::new(None);
+}
+
+pub fn set(monitor_id: u64, filter: Filter) {
+    *BREAKPOINT.write().unwrap() = Some((monitor_id, filter));
+}
+
+pub fn clear() {
+    *BREAKPOINT.write().unwrap() = None;
+}
+
+// Returns true if the monitor's breakpoint was the one set
+pub fn clear_owned_by(monitor_id: u64) -> bool {
+    let mut breakpoint = BREAKPOINT.write().unwrap();
+    if matches!(breakpoint.as_ref(), Some((owner, _)) if *owner == monitor_id) {
+        *breakpoint = None;
+        true
+    } else {
+        false
+    }
+}
+
+pub fn hit(player_id: u32, from_client: bool, buf: &[u8]) -> bool {
+    match BREAKPOINT.read().unwrap().as_ref() {
+        Some((_, filter)) => filter.matches(&PacketContext::new(player_id, from_client, buf)),
+        None => false,
+    }
+}
@@ -1,18 +1,22 @@
 use chrono::{DateTime, Local};
 use eo::{
-    data::{encode_number, EOByte, StreamBuilder},
+    data::{encode_number, StreamBuilder},
     net::PacketProcessor,
     protocol::{PacketAction, PacketFamily},
 };
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 const PACKET_HEADER_SIZE: usize = 2;
 const PACKET_LENGTH_SIZE: usize = 2;
 
 use crate::PacketBuf;
 
+// Anything a bus can carry packets over: plain TCP, TLS, or a bridged WebSocket
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
 pub struct Bus {
-    socket: TcpStream,
+    socket: Box<dyn Stream>,
     pub packet_processor: PacketProcessor,
     timestamp: DateTime<Local>,
     name: String,
@@ -22,9 +26,9 @@ pub struct Bus {
 }
 
 impl Bus {
-    pub fn new(socket: TcpStream, name: String) -> Self {
+    pub fn new<S: Stream + 'static>(socket: S, name: String) -> Self {
         Self {
-            socket,
+            socket: Box::new(socket),
             packet_processor: PacketProcessor::new(),
             timestamp: Local::now(),
             name,
@@ -34,6 +38,10 @@ impl Bus {
         }
     }
 
+    pub fn timestamp(&self) -> DateTime<Local> {
+        self.timestamp
+    }
+
     pub async fn send_raw(&mut self, mut data: PacketBuf) -> std::io::Result<()> {
         let packet_size = data.len();
 
@@ -49,18 +57,8 @@ impl Bus {
         data.insert(0, length_bytes[1]);
         data.insert(0, length_bytes[0]);
 
-        match self.socket.try_write(&data) {
-            Ok(num_of_bytes_written) => {
-                if num_of_bytes_written != packet_size + PACKET_LENGTH_SIZE {
-                    error!(
-                        "Written bytes ({}) doesn't match packet size ({})",
-                        num_of_bytes_written, packet_size
-                    );
-                }
-            }
-            _ => {
-                error!("Error writing to socket");
-            }
+        if let Err(e) = self.socket.write_all(&data).await {
+            error!("Error writing to socket: {}", e);
         }
 
         Ok(())
@@ -93,18 +91,8 @@ impl Bus {
         buf.insert(0, length_bytes[1]);
         buf.insert(0, length_bytes[0]);
 
-        match self.socket.try_write(&buf) {
-            Ok(num_of_bytes_written) => {
-                if num_of_bytes_written != packet_size + PACKET_LENGTH_SIZE {
-                    error!(
-                        "Written bytes ({}) doesn't match packet size ({})",
-                        num_of_bytes_written, packet_size
-                    );
-                }
-            }
-            _ => {
-                error!("Error writing to socket");
-            }
+        if let Err(e) = self.socket.write_all(&buf).await {
+            error!("Error writing to socket: {}", e);
         }
 
         Ok(())
@@ -119,7 +107,12 @@ impl Bus {
                         Some(Ok(_)) => {
                             let mut data_buf = self.buf.as_ref().unwrap().clone();
 
-                            debug!("{} Receive Raw: [{}] {:?}", self.name, self.timestamp.format("%M:%S.%f"), data_buf);
+                            debug!(
+                                "{} Receive Raw: [{}] {:?}",
+                                self.name,
+                                self.timestamp.format("%M:%S.%f"),
+                                data_buf
+                            );
                             self.packet_processor.decode(&mut data_buf);
 
                             self.timestamp = Local::now();
@@ -165,11 +158,8 @@ impl Bus {
             self.buf = Some(vec![0; length]);
         }
 
-        self.socket.readable().await.unwrap();
         if let Some(buf) = self.buf.as_mut() {
-            let mut recv_buf = vec![0; length - self.amount_read];
-
-            match self.socket.try_read(&mut recv_buf) {
+            match self.socket.read(&mut buf[self.amount_read..length]).await {
                 Ok(0) => {
                     return Some(Err(std::io::Error::new(
                         std::io::ErrorKind::BrokenPipe,
@@ -177,8 +167,6 @@ impl Bus {
                     )));
                 }
                 Ok(bytes_read) => {
-                    buf.splice(self.amount_read..self.amount_read + bytes_read, recv_buf);
-
                     self.amount_read += bytes_read;
 
                     if self.amount_read == length {
@@ -187,8 +175,8 @@ impl Bus {
                         return Some(Ok(()));
                     }
                 }
-                Err(_) => {
-                    return None;
+                Err(e) => {
+                    return Some(Err(e));
                 }
             }
         }
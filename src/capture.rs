@@ -0,0 +1,243 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Result, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local, TimeZone};
+
+use crate::{
+    filter::{Filter, PacketContext},
+    settings::Capture,
+    PacketBuf,
+};
+
+// Capture files start with MAGIC followed by records of:
+//
+//   tag: u8, time: i64 microseconds since the epoch, session id: u32
+//
+// then for a session record the client address as a u16 length and utf-8 string, and for a
+// packet record the player id (u32), direction (0 client, 1 server), a u32 length and the
+// packet bytes as relayed after decoding. Numbers are little endian.
+
+const MAGIC: &[u8; 8] = b"EOCAP\x00\x00\x01";
+const SESSION_TAG: u8 = 0;
+const PACKET_TAG: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Session {
+        time: DateTime<Local>,
+        session_id: u32,
+        addr: String,
+    },
+    Packet {
+        time: DateTime<Local>,
+        session_id: u32,
+        player_id: u32,
+        from_client: bool,
+        buf: PacketBuf,
+    },
+}
+
+impl Record {
+    pub fn time(&self) -> DateTime<Local> {
+        match self {
+            Self::Session { time, .. } | Self::Packet { time, .. } => *time,
+        }
+    }
+}
+
+pub struct CaptureWriter {
+    writer: BufWriter<File>,
+    session_id: u32,
+    filter: Option<Filter>,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path, session_id: u32, filter: Option<Filter>) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        Ok(Self {
+            writer,
+            session_id,
+            filter,
+        })
+    }
+
+    // Starts a capture for a session as configured, if captures are enabled
+    pub fn for_session(settings: &Capture, session_id: u32, addr: SocketAddr) -> Option<Self> {
+        if !settings.enabled {
+            return None;
+        }
+
+        let filter = match settings.filter.as_deref().map(Filter::parse) {
+            Some(Ok(filter)) => Some(filter),
+            Some(Err(e)) => {
+                error!("Capture filter ignored: {}", e);
+                None
+            }
+            None => None,
+        };
+
+        let now = Local::now();
+        let path = PathBuf::from(&settings.directory).join(format!(
+            "{}-{}.cap",
+            now.format("%Y%m%d-%H%M%S"),
+            session_id
+        ));
+
+        let created = fs::create_dir_all(&settings.directory)
+            .and_then(|_| Self::create(&path, session_id, filter));
+        let mut writer = match created {
+            Ok(writer) => writer,
+            Err(e) => {
+                error!("Failed to create capture {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        if let Err(e) = writer.write(&Record::Session {
+            time: now,
+            session_id,
+            addr: addr.to_string(),
+        }) {
+            error!("Failed to write capture {:?}: {}", path, e);
+            return None;
+        }
+
+        Some(writer)
+    }
+
+    pub fn packet(&mut self, player_id: u32, from_client: bool, buf: &[u8]) {
+        if let Some(filter) = &self.filter {
+            if !filter.matches(&PacketContext::new(player_id, from_client, buf)) {
+                return;
+            }
+        }
+
+        let record = Record::Packet {
+            time: Local::now(),
+            session_id: self.session_id,
+            player_id,
+            from_client,
+            buf: buf.to_vec(),
+        };
+
+        if let Err(e) = self.write(&record) {
+            error!("Failed to write capture: {}", e);
+        }
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<()> {
+        let (tag, time, session_id) = match record {
+            Record::Session {
+                time, session_id, ..
+            } => (SESSION_TAG, time, session_id),
+            Record::Packet {
+                time, session_id, ..
+            } => (PACKET_TAG, time, session_id),
+        };
+
+        self.writer.write_all(&[tag])?;
+        self.writer
+            .write_all(&time.timestamp_micros().to_le_bytes())?;
+        self.writer.write_all(&session_id.to_le_bytes())?;
+
+        match record {
+            Record::Session { addr, .. } => {
+                self.writer.write_all(&(addr.len() as u16).to_le_bytes())?;
+                self.writer.write_all(addr.as_bytes())?;
+            }
+            Record::Packet {
+                player_id,
+                from_client,
+                buf,
+                ..
+            } => {
+                self.writer.write_all(&player_id.to_le_bytes())?;
+                self.writer.write_all(&[if *from_client { 0 } else { 1 }])?;
+                self.writer.write_all(&(buf.len() as u32).to_le_bytes())?;
+                self.writer.write_all(buf)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_record(reader: &mut impl Read) -> Result<Option<Record>> {
+    let mut tag = [0; 1];
+    match reader.read_exact(&mut tag) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let micros = i64::from_le_bytes(read_array(reader)?);
+    let time = Local
+        .timestamp_micros(micros)
+        .single()
+        .ok_or_else(|| invalid("bad timestamp"))?;
+    let session_id = u32::from_le_bytes(read_array(reader)?);
+
+    match tag[0] {
+        SESSION_TAG => {
+            let length = u16::from_le_bytes(read_array(reader)?) as usize;
+            let mut addr = vec![0; length];
+            reader.read_exact(&mut addr)?;
+            Ok(Some(Record::Session {
+                time,
+                session_id,
+                addr: String::from_utf8(addr).map_err(|_| invalid("bad address"))?,
+            }))
+        }
+        PACKET_TAG => {
+            let player_id = u32::from_le_bytes(read_array(reader)?);
+            let [direction] = read_array(reader)?;
+            let length = u32::from_le_bytes(read_array(reader)?) as usize;
+            let mut buf = vec![0; length];
+            reader.read_exact(&mut buf)?;
+            Ok(Some(Record::Packet {
+                time,
+                session_id,
+                player_id,
+                from_client: direction == 0,
+                buf,
+            }))
+        }
+        _ => Err(invalid("unknown record")),
+    }
+}
+
+pub fn read(path: &Path) -> Result<Vec<Record>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let magic: [u8; 8] = read_array(&mut reader)?;
+    if &magic != MAGIC {
+        return Err(invalid("not an eoproxy capture"));
+    }
+
+    let mut records = Vec::new();
+    while let Some(record) = read_record(&mut reader)? {
+        records.push(record);
+    }
+    Ok(records)
+}
@@ -0,0 +1,359 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use eo::{
+    data::{EOShort, Serializeable, StreamReader},
+    protocol::{client, server, PacketAction, WarpType},
+};
+use lazy_static::lazy_static;
+use tokio::{
+    sync::mpsc::{self, UnboundedSender},
+    task,
+};
+
+use crate::SETTINGS;
+
+// Decodes Talk packets into chat messages, written a JSON object per line to a file per day
+// in the chat directory.
+//
+// A message is logged once, from whichever side sees it first: the sender's client when it
+// is connected through the proxy, otherwise the server relaying it to a session. Local and
+// party messages from the server only carry the sender's player id, so those are only
+// logged when their sender's client sends them through the proxy
+
+// Sessions all receive the same global messages and announcements within moments of each
+// other, so messages from the server are dropped if they were logged inside the window.
+// Messages from clients are always logged, since they're each something a player sent
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(2);
+const DEFAULT_LIMIT: usize = 100;
+
+lazy_static! {
+    static ref RECENT: Mutex<VecDeque<(Instant, Channel, String, String)>> =
+        Mutex::new(VecDeque::new());
+    static ref WRITER: UnboundedSender<ChatMessage> = start_writer();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Local,
+    Global,
+    Guild,
+    Party,
+    Private,
+    Announcement,
+    Admin,
+    Server,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub time: DateTime<Local>,
+    pub session_id: u32,
+    pub channel: Channel,
+    pub map_id: Option<EOShort>,
+    pub sender: Option<String>,
+    pub receiver: Option<String>,
+    pub message: String,
+}
+
+// A message before it is known who is talking to whom
+pub struct Talk {
+    pub channel: Channel,
+    // The sender for messages from the server, the receiver for messages from the client
+    pub name: Option<String>,
+    pub message: String,
+}
+
+// Writes happen on a thread of their own so sessions never wait on the disk
+#[derive(Default)]
+struct ChatLog {
+    // The date of the open file, as in its name
+    date: String,
+    file: Option<File>,
+}
+
+impl ChatLog {
+    fn write(&mut self, message: &ChatMessage) -> io::Result<()> {
+        let date = message.time.format("%Y%m%d").to_string();
+        if self.file.is_none() || self.date != date {
+            let directory = Path::new(&SETTINGS.chat.directory);
+            fs::create_dir_all(directory)?;
+            self.file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(directory.join(format!("chat-{}.jsonl", date)))?,
+            );
+            self.date = date;
+        }
+
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        self.file.as_mut().unwrap().write_all(&line)
+    }
+}
+
+pub fn enabled() -> bool {
+    SETTINGS.chat.enabled
+}
+
+fn read<T: Serializeable + Default>(payload: &[u8]) -> T {
+    let mut packet = T::default();
+    packet.deserialize(&StreamReader::new(payload));
+    packet
+}
+
+// payload doesn't include the sequence byte
+pub fn client_talk(action: PacketAction, payload: &[u8]) -> Option<Talk> {
+    let (channel, name, message) = match action {
+        PacketAction::Report => {
+            let report = read::<client::talk::Report>(payload);
+            (Channel::Local, None, report.message)
+        }
+        PacketAction::Msg => {
+            let message = read::<client::talk::Msg>(payload);
+            (Channel::Global, None, message.message)
+        }
+        PacketAction::Request => {
+            let request = read::<client::talk::Request>(payload);
+            (Channel::Guild, None, request.message)
+        }
+        PacketAction::Open => {
+            let open = read::<client::talk::Open>(payload);
+            (Channel::Party, None, open.message)
+        }
+        PacketAction::Tell => {
+            let tell = read::<client::talk::Tell>(payload);
+            (Channel::Private, Some(tell.name), tell.message)
+        }
+        PacketAction::Announce => {
+            let announce = read::<client::talk::Announce>(payload);
+            (Channel::Announcement, None, announce.message)
+        }
+        PacketAction::Admin => {
+            let admin = read::<client::talk::Admin>(payload);
+            (Channel::Admin, None, admin.message)
+        }
+        _ => return None,
+    };
+
+    Some(Talk {
+        channel,
+        name,
+        message,
+    })
+}
+
+pub fn server_talk(action: PacketAction, payload: &[u8]) -> Option<Talk> {
+    let (channel, name, message) = match action {
+        PacketAction::Msg => {
+            let message = read::<server::talk::Msg>(payload);
+            (Channel::Global, Some(message.player_name), message.message)
+        }
+        PacketAction::Request => {
+            let request = read::<server::talk::Request>(payload);
+            (Channel::Guild, Some(request.player_name), request.message)
+        }
+        PacketAction::Tell => {
+            let tell = read::<server::talk::Tell>(payload);
+            (Channel::Private, Some(tell.player_name), tell.message)
+        }
+        PacketAction::Announce => {
+            let announce = read::<server::talk::Announce>(payload);
+            (
+                Channel::Announcement,
+                Some(announce.player_name),
+                announce.message,
+            )
+        }
+        PacketAction::Admin => {
+            let admin = read::<server::talk::Admin>(payload);
+            (Channel::Admin, Some(admin.player_name), admin.message)
+        }
+        PacketAction::Server => {
+            let server = read::<server::talk::Server>(payload);
+            (Channel::Server, None, server.message)
+        }
+        _ => return None,
+    };
+
+    Some(Talk {
+        channel,
+        name,
+        message,
+    })
+}
+
+// Finds the map a Warp_Agree takes the player to. Warps within a map leave it as it was
+pub fn warp_map(payload: &[u8]) -> Option<EOShort> {
+    // data is left at its default, a map switch to map 0, for warps within a map
+    let agree = read::<server::warp::Agree>(payload);
+    match (agree.warp_type, agree.data) {
+        (WarpType::MapSwitch, server::warp::AgreeData::MapSwitch(map_switch)) => {
+            Some(map_switch.map_id)
+        }
+        _ => None,
+    }
+}
+
+fn start_writer() -> UnboundedSender<ChatMessage> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<ChatMessage>();
+    thread::spawn(move || {
+        let mut log = ChatLog::default();
+        while let Some(message) = rx.blocking_recv() {
+            if let Err(e) = log.write(&message) {
+                error!("Failed to write chat log: {}", e);
+            }
+        }
+    });
+    tx
+}
+
+// Only messages relayed by the server can be duplicates
+fn duplicate(message: &ChatMessage, from_client: bool) -> bool {
+    let mut recent = RECENT.lock().unwrap();
+    let now = Instant::now();
+    while let Some((at, ..)) = recent.front() {
+        if now.duration_since(*at) <= DUPLICATE_WINDOW {
+            break;
+        }
+        recent.pop_front();
+    }
+
+    let sender = message.sender.clone().unwrap_or_default().to_lowercase();
+    let seen = recent.iter().any(|(_, channel, recent_sender, text)| {
+        *channel == message.channel && *recent_sender == sender && *text == message.message
+    });
+    if seen && !from_client {
+        return true;
+    }
+
+    recent.push_back((now, message.channel, sender, message.message.clone()));
+    false
+}
+
+// Returns false if the message had already been logged
+pub fn log(message: &ChatMessage, from_client: bool) -> bool {
+    if duplicate(message, from_client) {
+        return false;
+    }
+    let _ = WRITER.send(message.clone());
+    true
+}
+
+// Every field given has to match. Names match regardless of case, text anywhere in the message
+#[derive(Debug, Default, Deserialize)]
+pub struct Query {
+    #[serde(default)]
+    pub channel: Option<Channel>,
+    // Either the sender or the receiver
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub map_id: Option<EOShort>,
+    #[serde(default)]
+    pub since: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub until: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    fn matches(&self, message: &ChatMessage) -> bool {
+        let named = |name: &Option<String>, wanted: &str| matches!(name, Some(name) if name.eq_ignore_ascii_case(wanted));
+
+        self.channel
+            .is_none_or(|channel| channel == message.channel)
+            && self
+                .name
+                .as_deref()
+                .is_none_or(|name| named(&message.sender, name) || named(&message.receiver, name))
+            && self.text.as_deref().is_none_or(|text| {
+                message
+                    .message
+                    .to_lowercase()
+                    .contains(&text.to_lowercase())
+            })
+            && self
+                .map_id
+                .is_none_or(|map_id| message.map_id == Some(map_id))
+            && self.since.is_none_or(|since| message.time >= since)
+            && self.until.is_none_or(|until| message.time <= until)
+    }
+}
+
+// Newest first, reading back through the daily files on a blocking thread until the limit is
+// reached
+pub async fn search(query: Query) -> io::Result<Vec<ChatMessage>> {
+    task::spawn_blocking(move || search_files(&query))
+        .await
+        .map_err(io::Error::other)?
+}
+
+fn search_files(query: &Query) -> io::Result<Vec<ChatMessage>> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+
+    let mut files: Vec<PathBuf> = match fs::read_dir(&SETTINGS.chat.directory) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    // The names sort by date
+    files.sort();
+
+    let mut found = Vec::new();
+    for path in files.iter().rev() {
+        let mut matched: Vec<ChatMessage> = BufReader::new(File::open(path)?)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .filter(|message| query.matches(message))
+            .collect();
+        matched.reverse();
+        found.extend(matched);
+
+        if found.len() >= limit {
+            found.truncate(limit);
+            break;
+        }
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize;
+
+    #[test]
+    fn finds_the_map_of_a_warp() {
+        let mut map_switch = server::warp::AgreeMapSwitch::new();
+        map_switch.map_id = 42;
+        let mut agree = server::warp::Agree::new();
+        agree.warp_type = WarpType::MapSwitch;
+        agree.data = server::warp::AgreeData::MapSwitch(map_switch);
+        assert_eq!(warp_map(&serialize(&agree)), Some(42));
+
+        let mut agree = server::warp::Agree::new();
+        agree.warp_type = WarpType::Local;
+        agree.data = server::warp::AgreeData::None;
+        assert_eq!(warp_map(&serialize(&agree)), None);
+    }
+}
@@ -0,0 +1,117 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "The rusty endless online proxy")]
+pub struct Cli {
+    /// Show sessions and packets in a terminal UI instead of logging to stdout
+    #[arg(long)]
+    pub tui: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Convert capture files for use in other tools
+    Export(ExportArgs),
+    /// Compare the packets of two captures, exiting with 1 if they differ
+    Diff(DiffArgs),
+    /// Log in as a headless client and play through a scenario file
+    Bot(BotArgs),
+    /// Run many bots at once and report latency and errors
+    Load(LoadArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Capture files to export, merged in time order
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Write a pcapng file with the packets in synthesized TCP/IP frames
+    #[arg(long, value_name = "PATH")]
+    pub pcapng: Option<PathBuf>,
+
+    /// Write one JSON object per packet, with its decoded fields
+    #[arg(long, value_name = "PATH")]
+    pub jsonl: Option<PathBuf>,
+
+    /// Write a CSV summary of the packets
+    #[arg(long, value_name = "PATH")]
+    pub csv: Option<PathBuf>,
+
+    /// Write a Lua Wireshark dissector for the pcapng output
+    #[arg(long, value_name = "PATH")]
+    pub dissector: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    pub a: PathBuf,
+    pub b: PathBuf,
+
+    /// Also skip fields whose names contain this, on top of sequences and timestamps
+    #[arg(long, value_name = "FIELD")]
+    pub ignore: Vec<String>,
+
+    /// List unchanged packets too
+    #[arg(long)]
+    pub all: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct BotArgs {
+    /// Scenario of steps to run once in game
+    pub scenario: PathBuf,
+
+    /// Address of the proxy or server to connect to
+    #[arg(long, default_value = "127.0.0.1:8078")]
+    pub server: String,
+
+    /// Client version to connect as
+    #[arg(long, default_value = "0.0.28")]
+    pub version: String,
+
+    #[arg(long)]
+    pub username: String,
+
+    #[arg(long)]
+    pub password: String,
+
+    /// Character to play, defaults to the first on the account
+    #[arg(long)]
+    pub character: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct LoadArgs {
+    /// Scenarios handed out to sessions in turn
+    #[arg(required = true)]
+    pub scenarios: Vec<PathBuf>,
+
+    /// Address of the proxy or server to connect to
+    #[arg(long, default_value = "127.0.0.1:8078")]
+    pub server: String,
+
+    /// Client version to connect as
+    #[arg(long, default_value = "0.0.28")]
+    pub version: String,
+
+    /// Number of sessions to run
+    #[arg(long, default_value_t = 10)]
+    pub sessions: usize,
+
+    /// Seconds over which to spread out the session starts
+    #[arg(long, default_value_t = 10)]
+    pub ramp_up_secs: u64,
+
+    /// Account name, with {} replaced by the session number
+    #[arg(long, default_value = "bot{}")]
+    pub username: String,
+
+    #[arg(long)]
+    pub password: String,
+}
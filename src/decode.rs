@@ -0,0 +1,533 @@
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+use eo::{
+    data::{Serializeable, StreamReader},
+    protocol::{client, server, PacketAction, PacketFamily},
+};
+use serde_json::Value;
+
+use crate::{serialize, PacketBuf};
+
+thread_local! {
+    static READING: Cell<bool> = const { Cell::new(false) };
+}
+
+static QUIET_READS: Once = Once::new();
+
+// eo's readers index past the end of payloads shorter than their type expects. The panic is
+// caught and kept out of the log, so a truncated packet can't take its session down with it
+fn read<T: Serializeable>(packet: &mut T, reader: &StreamReader) -> bool {
+    QUIET_READS.call_once(|| {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !reading() {
+                hook(info);
+            }
+        }));
+    });
+
+    READING.with(|reading| reading.set(true));
+    let read = panic::catch_unwind(AssertUnwindSafe(|| packet.deserialize(reader))).is_ok();
+    READING.with(|reading| reading.set(false));
+    read
+}
+
+// Whether this thread is in the middle of a read that may panic
+pub fn reading() -> bool {
+    READING.with(Cell::get)
+}
+
+// Maps packet ids to the protocol types used to decode them, for every packet in eo's protocol.
+// Ids it doesn't define are shown as raw bytes
+macro_rules! packets {
+    (
+        client { $($c_family:ident $c_action:ident => $c_type:ty,)* }
+        server { $($s_family:ident $s_action:ident => $s_type:ty,)* }
+    ) => {
+        fn decode_payload(
+            from_client: bool,
+            family: PacketFamily,
+            action: PacketAction,
+            reader: &StreamReader,
+        ) -> Option<Value> {
+            match (from_client, family, action) {
+                $(
+                    (true, PacketFamily::$c_family, PacketAction::$c_action) => {
+                        let mut packet = <$c_type>::new();
+                        if !read(&mut packet, reader) {
+                            return None;
+                        }
+                        serde_json::to_value(&packet).ok()
+                    }
+                )*
+                $(
+                    (false, PacketFamily::$s_family, PacketAction::$s_action) => {
+                        let mut packet = <$s_type>::new();
+                        if !read(&mut packet, reader) {
+                            return None;
+                        }
+                        serde_json::to_value(&packet).ok()
+                    }
+                )*
+                _ => None,
+            }
+        }
+
+        // Deserializes the payload and serializes it again, returning the bytes the reader didn't
+        // get to along with the re-encoded payload, which is None when the reader ran off the end
+        fn round_trip_payload(
+            from_client: bool,
+            family: PacketFamily,
+            action: PacketAction,
+            payload: &[u8],
+        ) -> Option<(usize, Option<PacketBuf>)> {
+            let reader = StreamReader::new(payload);
+            match (from_client, family, action) {
+                $(
+                    (true, PacketFamily::$c_family, PacketAction::$c_action) => {
+                        let mut packet = <$c_type>::new();
+                        if !read(&mut packet, &reader) {
+                            return Some((0, None));
+                        }
+                        Some((reader.remaining(), Some(serialize(&packet))))
+                    }
+                )*
+                $(
+                    (false, PacketFamily::$s_family, PacketAction::$s_action) => {
+                        let mut packet = <$s_type>::new();
+                        if !read(&mut packet, &reader) {
+                            return Some((0, None));
+                        }
+                        Some((reader.remaining(), Some(serialize(&packet))))
+                    }
+                )*
+                _ => None,
+            }
+        }
+
+        fn encode_payload(
+            from_client: bool,
+            family: PacketFamily,
+            action: PacketAction,
+            value: Value,
+        ) -> Option<PacketBuf> {
+            match (from_client, family, action) {
+                $(
+                    (true, PacketFamily::$c_family, PacketAction::$c_action) => {
+                        let packet: $c_type = serde_json::from_value(value).ok()?;
+                        Some(serialize(&packet))
+                    }
+                )*
+                $(
+                    (false, PacketFamily::$s_family, PacketAction::$s_action) => {
+                        let packet: $s_type = serde_json::from_value(value).ok()?;
+                        Some(serialize(&packet))
+                    }
+                )*
+                _ => None,
+            }
+        }
+    };
+}
+
+packets! {
+    client {
+        Init Init => client::init::Init,
+        Message List => client::message::List,
+        Message Ping => client::message::Ping,
+        Connection Accept => client::connection::Accept,
+        Connection Ping => client::connection::Ping,
+        Account Request => client::account::Request,
+        Account Create => client::account::Create,
+        Account Agree => client::account::Agree,
+        Character Request => client::character::Request,
+        Character Create => client::character::Create,
+        Character Remove => client::character::Remove,
+        Character Take => client::character::Take,
+        Login Request => client::login::Request,
+        Welcome Request => client::welcome::Request,
+        Welcome Msg => client::welcome::Msg,
+        Welcome Agree => client::welcome::Agree,
+        AdminInteract Tell => client::admininteract::Tell,
+        AdminInteract Report => client::admininteract::Report,
+        Global Remove => client::global::Remove,
+        Global Player => client::global::Player,
+        Global Open => client::global::Open,
+        Global Close => client::global::Close,
+        Talk Request => client::talk::Request,
+        Talk Open => client::talk::Open,
+        Talk Msg => client::talk::Msg,
+        Talk Tell => client::talk::Tell,
+        Talk Report => client::talk::Report,
+        Talk Admin => client::talk::Admin,
+        Talk Announce => client::talk::Announce,
+        Attack Use => client::attack::Use,
+        Chair Request => client::chair::Request,
+        Sit Request => client::sit::Request,
+        Emote Report => client::emote::Report,
+        Face Player => client::face::Player,
+        Walk Admin => client::walk::Admin,
+        Walk Spec => client::walk::Spec,
+        Walk Player => client::walk::Player,
+        Bank Open => client::bank::Open,
+        Bank Add => client::bank::Add,
+        Bank Take => client::bank::Take,
+        Locker Add => client::locker::Add,
+        Locker Take => client::locker::Take,
+        Locker Open => client::locker::Open,
+        Locker Buy => client::locker::Buy,
+        Citizen Request => client::citizen::Request,
+        Citizen Accept => client::citizen::Accept,
+        Citizen Reply => client::citizen::Reply,
+        Citizen Remove => client::citizen::Remove,
+        Citizen Open => client::citizen::Open,
+        Shop Create => client::shop::Create,
+        Shop Buy => client::shop::Buy,
+        Shop Sell => client::shop::Sell,
+        Shop Open => client::shop::Open,
+        StatSkill Open => client::statskill::Open,
+        StatSkill Take => client::statskill::Take,
+        StatSkill Remove => client::statskill::Remove,
+        StatSkill Add => client::statskill::Add,
+        StatSkill Junk => client::statskill::Junk,
+        Item Use => client::item::Use,
+        Item Drop => client::item::Drop,
+        Item Junk => client::item::Junk,
+        Item Get => client::item::Get,
+        Barber Open => client::barber::Open,
+        Barber Buy => client::barber::Buy,
+        Board Remove => client::board::Remove,
+        Board Create => client::board::Create,
+        Board Take => client::board::Take,
+        Board Open => client::board::Open,
+        Jukebox Open => client::jukebox::Open,
+        Jukebox Msg => client::jukebox::Msg,
+        Jukebox Use => client::jukebox::Use,
+        Warp Accept => client::warp::Accept,
+        Warp Take => client::warp::Take,
+        Paperdoll Request => client::paperdoll::Request,
+        Paperdoll Remove => client::paperdoll::Remove,
+        Paperdoll Add => client::paperdoll::Add,
+        Book Request => client::book::Request,
+        Players Accept => client::players::Accept,
+        Players Request => client::players::Request,
+        Players List => client::players::List,
+        Door Open => client::door::Open,
+        Chest Open => client::chest::Open,
+        Chest Add => client::chest::Add,
+        Chest Take => client::chest::Take,
+        Refresh Request => client::refresh::Request,
+        Range Request => client::range::Request,
+        PlayerRange Request => client::playerrange::Request,
+        NPCRange Request => client::npcrange::Request,
+        Party Request => client::party::Request,
+        Party Accept => client::party::Accept,
+        Party Remove => client::party::Remove,
+        Party Take => client::party::Take,
+        Guild Request => client::guild::Request,
+        Guild Accept => client::guild::Accept,
+        Guild Remove => client::guild::Remove,
+        Guild Agree => client::guild::Agree,
+        Guild Create => client::guild::Create,
+        Guild Player => client::guild::Player,
+        Guild Take => client::guild::Take,
+        Guild Use => client::guild::Use,
+        Guild Buy => client::guild::Buy,
+        Guild Open => client::guild::Open,
+        Guild Tell => client::guild::Tell,
+        Guild Report => client::guild::Report,
+        Guild Junk => client::guild::Junk,
+        Guild Kick => client::guild::Kick,
+        Guild Rank => client::guild::Rank,
+        Spell Request => client::spell::Request,
+        Spell TargetSelf => client::spell::TargetSelf,
+        Spell TargetOther => client::spell::TargetOther,
+        Spell TargetGroup => client::spell::TargetGroup,
+        Spell Use => client::spell::Use,
+        Trade Request => client::trade::Request,
+        Trade Accept => client::trade::Accept,
+        Trade Remove => client::trade::Remove,
+        Trade Agree => client::trade::Agree,
+        Trade Add => client::trade::Add,
+        Trade Close => client::trade::Close,
+        Quest Use => client::quest::Use,
+        Quest Accept => client::quest::Accept,
+        Quest List => client::quest::List,
+        Marriage Open => client::marriage::Open,
+        Marriage Request => client::marriage::Request,
+        Priest Accept => client::priest::Accept,
+        Priest Open => client::priest::Open,
+        Priest Request => client::priest::Request,
+        Priest Use => client::priest::Use,
+    }
+    server {
+        Init Init => server::init::Init,
+        Connection Player => server::connection::Player,
+        Account Reply => server::account::Reply,
+        Character Reply => server::character::Reply,
+        Character Player => server::character::Player,
+        Login Reply => server::login::Reply,
+        Welcome Reply => server::welcome::Reply,
+        AdminInteract Reply => server::admininteract::Reply,
+        AdminInteract Remove => server::admininteract::Remove,
+        AdminInteract Agree => server::admininteract::Agree,
+        AdminInteract List => server::admininteract::List,
+        AdminInteract Tell => server::admininteract::Tell,
+        Talk Request => server::talk::Request,
+        Talk Open => server::talk::Open,
+        Talk Msg => server::talk::Msg,
+        Talk Tell => server::talk::Tell,
+        Talk Player => server::talk::Player,
+        Talk Admin => server::talk::Admin,
+        Talk Announce => server::talk::Announce,
+        Talk Server => server::talk::Server,
+        Talk Reply => server::talk::Reply,
+        Talk List => server::talk::List,
+        Talk Spec => server::talk::Spec,
+        Message Open => server::message::Open,
+        Message Accept => server::message::Accept,
+        Message Pong => server::message::Pong,
+        Message Close => server::message::Close,
+        Attack Player => server::attack::Player,
+        Avatar Reply => server::avatar::Reply,
+        Avatar Remove => server::avatar::Remove,
+        Avatar Agree => server::avatar::Agree,
+        Avatar Admin => server::avatar::Admin,
+        Chair Player => server::chair::Player,
+        Chair Reply => server::chair::Reply,
+        Chair Close => server::chair::Close,
+        Sit Player => server::sit::Player,
+        Sit Close => server::sit::Close,
+        Sit Remove => server::sit::Remove,
+        Sit Reply => server::sit::Reply,
+        Emote Player => server::emote::Player,
+        Effect Player => server::effect::Player,
+        Effect Use => server::effect::Use,
+        Effect Report => server::effect::Report,
+        Effect Agree => server::effect::Agree,
+        Effect Spec => server::effect::Spec,
+        Effect TargetOther => server::effect::TargetOther,
+        Effect Admin => server::effect::Admin,
+        Face Player => server::face::Player,
+        Players Agree => server::players::Agree,
+        Players Remove => server::players::Remove,
+        Players Ping => server::players::Ping,
+        Players Pong => server::players::Pong,
+        Players Net3 => server::players::Net3,
+        Walk Player => server::walk::Player,
+        Walk Reply => server::walk::Reply,
+        Walk Close => server::walk::Close,
+        Walk Open => server::walk::Open,
+        Bank Open => server::bank::Open,
+        Bank Reply => server::bank::Reply,
+        Locker Reply => server::locker::Reply,
+        Locker Get => server::locker::Get,
+        Locker Open => server::locker::Open,
+        Locker Buy => server::locker::Buy,
+        Locker Spec => server::locker::Spec,
+        Citizen Reply => server::citizen::Reply,
+        Citizen Remove => server::citizen::Remove,
+        Citizen Open => server::citizen::Open,
+        Citizen Accept => server::citizen::Accept,
+        Citizen Request => server::citizen::Request,
+        Shop Create => server::shop::Create,
+        Shop Buy => server::shop::Buy,
+        Shop Sell => server::shop::Sell,
+        Shop Open => server::shop::Open,
+        StatSkill Open => server::statskill::Open,
+        StatSkill Reply => server::statskill::Reply,
+        StatSkill Take => server::statskill::Take,
+        StatSkill Remove => server::statskill::Remove,
+        StatSkill Player => server::statskill::Player,
+        StatSkill Accept => server::statskill::Accept,
+        StatSkill Junk => server::statskill::Junk,
+        Item Reply => server::item::Reply,
+        Item Drop => server::item::Drop,
+        Item Add => server::item::Add,
+        Item Remove => server::item::Remove,
+        Item Junk => server::item::Junk,
+        Item Get => server::item::Get,
+        Item Obtain => server::item::Obtain,
+        Item Kick => server::item::Kick,
+        Item Agree => server::item::Agree,
+        Item Spec => server::item::Spec,
+        Item Accept => server::item::Accept,
+        Barber Open => server::barber::Open,
+        Barber Agree => server::barber::Agree,
+        Board Player => server::board::Player,
+        Board Open => server::board::Open,
+        Jukebox Agree => server::jukebox::Agree,
+        Jukebox Reply => server::jukebox::Reply,
+        Jukebox Open => server::jukebox::Open,
+        Jukebox Msg => server::jukebox::Msg,
+        Jukebox Player => server::jukebox::Player,
+        Jukebox Use => server::jukebox::Use,
+        Warp Request => server::warp::Request,
+        Warp Agree => server::warp::Agree,
+        Paperdoll Reply => server::paperdoll::Reply,
+        Paperdoll Remove => server::paperdoll::Remove,
+        Paperdoll Agree => server::paperdoll::Agree,
+        Paperdoll Ping => server::paperdoll::Ping,
+        Book Reply => server::book::Reply,
+        Door Open => server::door::Open,
+        Door Close => server::door::Close,
+        Chest Open => server::chest::Open,
+        Chest Reply => server::chest::Reply,
+        Chest Get => server::chest::Get,
+        Chest Agree => server::chest::Agree,
+        Chest Spec => server::chest::Spec,
+        Refresh Reply => server::refresh::Reply,
+        Range Reply => server::range::Reply,
+        Party Request => server::party::Request,
+        Party Create => server::party::Create,
+        Party Add => server::party::Add,
+        Party Remove => server::party::Remove,
+        Party Close => server::party::Close,
+        Party List => server::party::List,
+        Party Agree => server::party::Agree,
+        Party TargetGroup => server::party::TargetGroup,
+        Party Reply => server::party::Reply,
+        Guild Reply => server::guild::Reply,
+        Guild Request => server::guild::Request,
+        Guild Create => server::guild::Create,
+        Guild Take => server::guild::Take,
+        Guild Rank => server::guild::Rank,
+        Guild Sell => server::guild::Sell,
+        Guild Buy => server::guild::Buy,
+        Guild Open => server::guild::Open,
+        Guild Tell => server::guild::Tell,
+        Guild Report => server::guild::Report,
+        Guild Agree => server::guild::Agree,
+        Guild Accept => server::guild::Accept,
+        Guild Kick => server::guild::Kick,
+        Spell Request => server::spell::Request,
+        Spell TargetSelf => server::spell::TargetSelf,
+        Spell Player => server::spell::Player,
+        Spell TargetGroup => server::spell::TargetGroup,
+        Spell TargetOther => server::spell::TargetOther,
+        Trade Request => server::trade::Request,
+        Trade Open => server::trade::Open,
+        Trade Reply => server::trade::Reply,
+        Trade Admin => server::trade::Admin,
+        Trade Use => server::trade::Use,
+        Trade Spec => server::trade::Spec,
+        Trade Agree => server::trade::Agree,
+        Trade Close => server::trade::Close,
+        Npc Reply => server::npc::Reply,
+        Npc Spec => server::npc::Spec,
+        Npc Agree => server::npc::Agree,
+        Npc Accept => server::npc::Accept,
+        Npc Junk => server::npc::Junk,
+        Npc Dialog => server::npc::Dialog,
+        Npc Player => server::npc::Player,
+        Cast Reply => server::cast::Reply,
+        Cast Spec => server::cast::Spec,
+        Cast Accept => server::cast::Accept,
+        Quest Report => server::quest::Report,
+        Quest Dialog => server::quest::Dialog,
+        Quest List => server::quest::List,
+        Arena Drop => server::arena::Drop,
+        Arena Use => server::arena::Use,
+        Arena Spec => server::arena::Spec,
+        Arena Accept => server::arena::Accept,
+        Marriage Open => server::marriage::Open,
+        Marriage Reply => server::marriage::Reply,
+        Priest Open => server::priest::Open,
+        Priest Reply => server::priest::Reply,
+        Priest Request => server::priest::Request,
+        Recover Player => server::recover::Player,
+        Recover List => server::recover::List,
+        Recover Reply => server::recover::Reply,
+        Recover TargetGroup => server::recover::TargetGroup,
+        Recover Agree => server::recover::Agree,
+        Music Player => server::music::Player,
+    }
+}
+
+// Client packets outside of the Init family start with a sequence number
+pub fn has_sequence(from_client: bool, family: PacketFamily) -> bool {
+    from_client && family != PacketFamily::Init
+}
+
+// Takes a whole packet as sent over the WebSocket: action, family, then payload
+pub fn decode(from_client: bool, buf: &[u8]) -> Option<Value> {
+    if buf.len() < 2 {
+        return None;
+    }
+
+    let action = PacketAction::from_byte(buf[0])?;
+    let family = PacketFamily::from_byte(buf[1])?;
+
+    let payload = if has_sequence(from_client, family) {
+        buf.get(3..)?
+    } else {
+        &buf[2..]
+    };
+
+    let reader = StreamReader::new(payload);
+    decode_payload(from_client, family, action, &reader)
+}
+
+// None when the packet has no protocol type to check against
+pub fn round_trip(from_client: bool, buf: &[u8]) -> Option<(&[u8], usize, Option<PacketBuf>)> {
+    let action = PacketAction::from_byte(*buf.first()?)?;
+    let family = PacketFamily::from_byte(*buf.get(1)?)?;
+
+    let payload = if has_sequence(from_client, family) {
+        buf.get(3..)?
+    } else {
+        &buf[2..]
+    };
+
+    let (remaining, reencoded) = round_trip_payload(from_client, family, action, payload)?;
+    Some((payload, remaining, reencoded))
+}
+
+pub fn packet_name(buf: &[u8]) -> String {
+    if buf.len() < 2 {
+        return "Invalid".to_string();
+    }
+
+    let family = match PacketFamily::from_byte(buf[1]) {
+        Some(family) => format!("{:?}", family),
+        None => format!("Unknown({})", buf[1]),
+    };
+    let action = match PacketAction::from_byte(buf[0]) {
+        Some(action) => format!("{:?}", action),
+        None => format!("Unknown({})", buf[0]),
+    };
+
+    format!("{}_{}", family, action)
+}
+
+// Builds a whole packet from a decoded value. Client packets need the sequence number to send
+pub fn encode(
+    from_client: bool,
+    family: PacketFamily,
+    action: PacketAction,
+    sequence: Option<u8>,
+    value: Value,
+) -> Option<PacketBuf> {
+    let mut buf = vec![action.to_byte(), family.to_byte()];
+    if has_sequence(from_client, family) {
+        buf.push(sequence?);
+    }
+    buf.append(&mut encode_payload(from_client, family, action, value)?);
+    Some(buf)
+}
+
+pub fn family_from_name(name: &str) -> Option<PacketFamily> {
+    (0..=255)
+        .filter_map(PacketFamily::from_byte)
+        .find(|family| format!("{:?}", family).eq_ignore_ascii_case(name))
+}
+
+pub fn action_from_name(name: &str) -> Option<PacketAction> {
+    (0..=255)
+        .filter_map(PacketAction::from_byte)
+        .find(|action| format!("{:?}", action).eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,301 @@
+use std::error::Error;
+
+use eo::protocol::PacketFamily;
+use serde_json::Value;
+
+use crate::{
+    capture::{self, Record},
+    cli::DiffArgs,
+    decode, PacketBuf,
+};
+
+// Lines up the packets of two captures by direction, family and action, then compares the
+// decoded fields of packets that line up. Fields whose names contain any of VOLATILE_FIELDS
+// (or --ignore) are left out, as they differ between otherwise identical runs
+
+const VOLATILE_FIELDS: &[&str] = &["sequence", "timestamp", "time"];
+
+struct Packet {
+    from_client: bool,
+    buf: PacketBuf,
+}
+
+impl Packet {
+    fn key(&self) -> Key {
+        (self.from_client, self.buf[0], self.buf[1])
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} {}",
+            if self.from_client { "Client" } else { "Server" },
+            decode::packet_name(&self.buf)
+        )
+    }
+
+    fn payload(&self) -> &[u8] {
+        let skip = match PacketFamily::from_byte(self.buf[1]) {
+            Some(family) if decode::has_sequence(self.from_client, family) => 3,
+            _ => 2,
+        };
+        self.buf.get(skip..).unwrap_or_default()
+    }
+}
+
+enum Change {
+    Same,
+    Changed(Vec<String>),
+    Removed,
+    Added,
+}
+
+fn packets(records: Vec<Record>) -> Vec<Packet> {
+    records
+        .into_iter()
+        .filter_map(|record| match record {
+            Record::Packet {
+                from_client, buf, ..
+            } if buf.len() >= 2 => Some(Packet { from_client, buf }),
+            _ => None,
+        })
+        .collect()
+}
+
+type Key = (bool, u8, u8);
+
+// Lengths of the longest common subsequences of a and each prefix of b, keeping one row
+fn lcs_lengths(a: &[Key], b: &[Key]) -> Vec<usize> {
+    let mut lengths = vec![0; b.len() + 1];
+    for x in a {
+        // lengths[j] from the row above
+        let mut diagonal = 0;
+        for (j, y) in b.iter().enumerate() {
+            let above = lengths[j + 1];
+            lengths[j + 1] = if x == y {
+                diagonal + 1
+            } else {
+                above.max(lengths[j])
+            };
+            diagonal = above;
+        }
+    }
+    lengths
+}
+
+// Hirschberg's algorithm: finds where the halves of a line up best in b, then recurses on
+// each side, so captures of any length fit in linear space. Matches are pushed in order
+fn lcs(a: &[Key], b: &[Key], offset: (usize, usize), matches: &mut Vec<(usize, usize)>) {
+    if a.is_empty() || b.is_empty() {
+        return;
+    }
+    if a.len() == 1 {
+        if let Some(j) = b.iter().position(|key| *key == a[0]) {
+            matches.push((offset.0, offset.1 + j));
+        }
+        return;
+    }
+
+    let mid = a.len() / 2;
+    let forward = lcs_lengths(&a[..mid], b);
+    let a_back: Vec<Key> = a[mid..].iter().rev().copied().collect();
+    let b_back: Vec<Key> = b.iter().rev().copied().collect();
+    let backward = lcs_lengths(&a_back, &b_back);
+
+    let split = (0..=b.len())
+        .max_by_key(|&j| forward[j] + backward[b.len() - j])
+        .unwrap_or_default();
+
+    lcs(&a[..mid], &b[..split], offset, matches);
+    lcs(
+        &a[mid..],
+        &b[split..],
+        (offset.0 + mid, offset.1 + split),
+        matches,
+    );
+}
+
+// Longest common subsequence of packet keys, after trimming what both start and end with
+fn align(a: &[Packet], b: &[Packet]) -> Vec<(Option<usize>, Option<usize>)> {
+    let prefix = a
+        .iter()
+        .zip(b)
+        .take_while(|(a, b)| a.key() == b.key())
+        .count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a.key() == b.key())
+        .count();
+
+    let a_mid: Vec<Key> = a[prefix..a.len() - suffix]
+        .iter()
+        .map(Packet::key)
+        .collect();
+    let b_mid: Vec<Key> = b[prefix..b.len() - suffix]
+        .iter()
+        .map(Packet::key)
+        .collect();
+    let mut matches = Vec::new();
+    lcs(&a_mid, &b_mid, (prefix, prefix), &mut matches);
+
+    let mut pairs: Vec<_> = (0..prefix).map(|i| (Some(i), Some(i))).collect();
+    let (mut i, mut j) = (prefix, prefix);
+    for (a_match, b_match) in matches
+        .into_iter()
+        .chain([(a.len() - suffix, b.len() - suffix)])
+    {
+        pairs.extend((i..a_match).map(|i| (Some(i), None)));
+        pairs.extend((j..b_match).map(|j| (None, Some(j))));
+        if a_match < a.len() - suffix {
+            pairs.push((Some(a_match), Some(b_match)));
+        }
+        (i, j) = (a_match + 1, b_match + 1);
+    }
+    pairs.extend((0..suffix).map(|k| (Some(a.len() - suffix + k), Some(b.len() - suffix + k))));
+    pairs
+}
+
+fn ignored(key: &str, ignore: &[String]) -> bool {
+    let key = key.to_lowercase();
+    VOLATILE_FIELDS.iter().any(|field| key.contains(field))
+        || ignore
+            .iter()
+            .any(|field| key.contains(&field.to_lowercase()))
+}
+
+fn diff_values(path: &str, a: &Value, b: &Value, ignore: &[String], changes: &mut Vec<String>) {
+    match (a, b) {
+        (Value::Object(a_fields), Value::Object(b_fields)) => {
+            let mut keys: Vec<&String> = a_fields.keys().chain(b_fields.keys()).collect();
+            keys.sort();
+            keys.dedup();
+
+            for key in keys {
+                if ignored(key, ignore) {
+                    continue;
+                }
+                let field = format!("{}.{}", path, key);
+                match (a_fields.get(key), b_fields.get(key)) {
+                    (Some(a), Some(b)) => diff_values(&field, a, b, ignore, changes),
+                    (Some(a), None) => changes.push(format!("{}: {} -> (missing)", field, a)),
+                    (None, Some(b)) => changes.push(format!("{}: (missing) -> {}", field, b)),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(a_items), Value::Array(b_items)) if a_items.len() == b_items.len() => {
+            for (index, (a, b)) in a_items.iter().zip(b_items).enumerate() {
+                diff_values(&format!("{}[{}]", path, index), a, b, ignore, changes);
+            }
+        }
+        _ if a != b => changes.push(format!("{}: {} -> {}", path, a, b)),
+        _ => {}
+    }
+}
+
+fn compare(a: &Packet, b: &Packet, ignore: &[String]) -> Change {
+    let mut changes = Vec::new();
+
+    match (
+        decode::decode(a.from_client, &a.buf),
+        decode::decode(b.from_client, &b.buf),
+    ) {
+        (Some(a), Some(b)) => diff_values("decoded", &a, &b, ignore, &mut changes),
+        _ if a.payload() != b.payload() => {
+            changes.push(format!("payload: {:?} -> {:?}", a.payload(), b.payload()))
+        }
+        _ => {}
+    }
+
+    if changes.is_empty() {
+        Change::Same
+    } else {
+        Change::Changed(changes)
+    }
+}
+
+// Returns whether the captures differ
+pub fn run(args: &DiffArgs) -> Result<bool, Box<dyn Error>> {
+    let a = packets(capture::read(&args.a).map_err(|e| format!("{:?}: {}", args.a, e))?);
+    let b = packets(capture::read(&args.b).map_err(|e| format!("{:?}: {}", args.b, e))?);
+
+    let (mut same, mut changed, mut removed, mut added) = (0, 0, 0, 0);
+
+    for (a_index, b_index) in align(&a, &b) {
+        let (change, packet) = match (a_index, b_index) {
+            (Some(i), Some(j)) => (compare(&a[i], &b[j], &args.ignore), &b[j]),
+            (Some(i), None) => (Change::Removed, &a[i]),
+            (None, Some(j)) => (Change::Added, &b[j]),
+            (None, None) => continue,
+        };
+
+        match change {
+            Change::Same => {
+                same += 1;
+                if args.all {
+                    println!("  {}", packet.describe());
+                }
+            }
+            Change::Changed(changes) => {
+                changed += 1;
+                println!("~ {}", packet.describe());
+                for change in changes {
+                    println!("      {}", change);
+                }
+            }
+            Change::Removed => {
+                removed += 1;
+                println!("- {}", packet.describe());
+            }
+            Change::Added => {
+                added += 1;
+                println!("+ {}", packet.describe());
+            }
+        }
+    }
+
+    println!(
+        "\n{} unchanged, {} changed, {} removed, {} added",
+        same, changed, removed, added
+    );
+
+    Ok(changed + removed + added > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(source: &str) -> Vec<Key> {
+        source.bytes().map(|byte| (true, byte, 0)).collect()
+    }
+
+    fn lcs_of(a: &str, b: &str) -> Vec<(usize, usize)> {
+        let mut matches = Vec::new();
+        lcs(&keys(a), &keys(b), (0, 0), &mut matches);
+        matches
+    }
+
+    #[test]
+    fn finds_a_longest_common_subsequence() {
+        for (a, b, length) in [
+            ("ABCBDAB", "BDCABA", 4),
+            ("AGGTAB", "GXTXAYB", 4),
+            ("ABC", "ABC", 3),
+            ("ABC", "DEF", 0),
+            ("", "ABC", 0),
+            ("AAAA", "AA", 2),
+        ] {
+            let matches = lcs_of(a, b);
+            assert_eq!(matches.len(), length, "{} {}", a, b);
+            // Matches are in order and really match
+            assert!(matches
+                .windows(2)
+                .all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+            assert!(matches
+                .iter()
+                .all(|&(i, j)| a.as_bytes()[i] == b.as_bytes()[j]));
+        }
+    }
+}
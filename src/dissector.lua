@@ -0,0 +1,120 @@
+-- Endless Online dissector generated by eoproxy export --dissector
+--
+-- Copy into your Wireshark plugins directory. Packets are expected unscrambled, as written by
+-- eoproxy export --pcapng, which also puts each packet's fields, decoded with the eo crate's
+-- protocol types, in its comment. They're shown under Fields without byte ranges, since the
+-- decoder doesn't report where each field came from
+
+local eo = Proto("eo", "Endless Online")
+
+local SERVER_PORT = @SERVER_PORT@
+local INIT_FAMILY = @INIT_FAMILY@
+
+local families = {
+@FAMILIES@
+}
+
+local actions = {
+@ACTIONS@
+}
+
+local f_length = ProtoField.uint16("eo.length", "Length")
+local f_action = ProtoField.uint8("eo.action", "Action", base.DEC, actions)
+local f_family = ProtoField.uint8("eo.family", "Family", base.DEC, families)
+local f_sequence = ProtoField.uint8("eo.sequence", "Sequence")
+local f_payload = ProtoField.bytes("eo.payload", "Payload")
+local f_fields = ProtoField.none("eo.fields", "Fields")
+local f_field = ProtoField.string("eo.field", "Field")
+
+eo.fields = { f_length, f_action, f_family, f_sequence, f_payload, f_fields, f_field }
+
+local f_comment = Field.new("frame.comment")
+
+local function eo_byte(b)
+    if b == 254 then
+        b = 1
+    elseif b == 0 then
+        b = 128
+    end
+    return b - 1
+end
+
+-- The comment's first line names the packet, the rest are its fields as "path: value"
+local function add_fields(tree)
+    local comment = f_comment()
+    if comment == nil then
+        return
+    end
+
+    local fields = nil
+    local first = true
+    for line in tostring(comment.value):gmatch("[^\n]+") do
+        if first then
+            first = false
+        else
+            fields = fields or tree:add(f_fields)
+            local value = line:match("^.-: (.*)$")
+            fields:add(f_field, value or line):set_text(line)
+        end
+    end
+end
+
+function eo.dissector(tvb, pinfo, tree)
+    local offset = 0
+    local names = {}
+
+    while offset < tvb:len() do
+        local remaining = tvb:len() - offset
+        if remaining < 2 then
+            pinfo.desegment_offset = offset
+            pinfo.desegment_len = DESEGMENT_ONE_MORE_SEGMENT
+            break
+        end
+
+        local length = eo_byte(tvb(offset, 1):uint()) + eo_byte(tvb(offset + 1, 1):uint()) * 253
+        if remaining < length + 2 then
+            pinfo.desegment_offset = offset
+            pinfo.desegment_len = length + 2 - remaining
+            break
+        end
+
+        local subtree = tree:add(eo, tvb(offset, length + 2))
+        subtree:add(f_length, tvb(offset, 2), length)
+
+        if length >= 2 then
+            local action = tvb(offset + 2, 1):uint()
+            local family = tvb(offset + 3, 1):uint()
+            local name = (families[family] or "Unknown") .. "_" .. (actions[action] or "Unknown")
+            table.insert(names, name)
+            subtree:append_text(", " .. name)
+            subtree:add(f_action, tvb(offset + 2, 1))
+            subtree:add(f_family, tvb(offset + 3, 1))
+
+            local payload = offset + 4
+            local from_client = pinfo.dst_port == SERVER_PORT
+            if from_client and family ~= INIT_FAMILY and length > 2 then
+                subtree:add(f_sequence, tvb(payload, 1))
+                payload = payload + 1
+            end
+
+            local payload_length = offset + 2 + length - payload
+            if payload_length > 0 then
+                subtree:add(f_payload, tvb(payload, payload_length))
+            end
+
+            -- Exported captures have one packet per segment, so the comment is this packet's
+            if offset == 0 and remaining == length + 2 then
+                add_fields(subtree)
+            end
+        end
+
+        offset = offset + 2 + length
+    end
+
+    if #names > 0 then
+        pinfo.cols.protocol = "EO"
+        pinfo.cols.info = table.concat(names, ", ")
+    end
+end
+
+DissectorTable.get("tcp.port"):add(SERVER_PORT, eo)
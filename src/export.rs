@@ -0,0 +1,426 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Result, Write},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::Path,
+};
+
+use chrono::{DateTime, Local, SecondsFormat};
+use eo::{
+    data::encode_number,
+    protocol::{PacketAction, PacketFamily},
+};
+
+use serde_json::Value;
+
+use crate::{
+    capture::{self, Record},
+    cli::ExportArgs,
+    decode,
+};
+
+// Captures hold packets after decoding, so they're written out as if they had gone over a
+// plain TCP connection between the client and a server at SERVER_ADDR, with a handshake at the
+// start of each session. Each packet's decoded fields are attached as its comment, a
+// "path: value" per line, which the dissector shows as a field tree
+
+const SERVER_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 8078);
+
+const LINKTYPE_RAW: u16 = 101;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const OPTION_COMMENT: u16 = 1;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+const DISSECTOR: &str = include_str!("dissector.lua");
+
+pub fn run(args: &ExportArgs) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let mut records = Vec::new();
+    for input in &args.inputs {
+        records.append(&mut capture::read(input).map_err(|e| format!("{:?}: {}", input, e))?);
+    }
+    // Sessions are stored separately, so merge them back into one timeline
+    records.sort_by_key(|record| record.time());
+
+    if let Some(path) = &args.pcapng {
+        pcapng(&records, path)?;
+        println!("Wrote {} records to {:?}", records.len(), path);
+    }
+
+    if let Some(path) = &args.jsonl {
+        jsonl(&records, path)?;
+        println!("Wrote packets to {:?}", path);
+    }
+
+    if let Some(path) = &args.csv {
+        csv(&records, path)?;
+        println!("Wrote packet summary to {:?}", path);
+    }
+
+    if let Some(path) = &args.dissector {
+        fs::write(path, dissector())?;
+        println!("Wrote dissector to {:?}", path);
+    }
+
+    Ok(())
+}
+
+pub fn dissector() -> String {
+    let families: Vec<String> = (0..=255)
+        .filter_map(PacketFamily::from_byte)
+        .map(|family| format!("    [{}] = \"{:?}\",", family.to_byte(), family))
+        .collect();
+    let actions: Vec<String> = (0..=255)
+        .filter_map(PacketAction::from_byte)
+        .map(|action| format!("    [{}] = \"{:?}\",", action.to_byte(), action))
+        .collect();
+
+    DISSECTOR
+        .replace("@SERVER_PORT@", &SERVER_ADDR.port().to_string())
+        .replace("@INIT_FAMILY@", &PacketFamily::Init.to_byte().to_string())
+        .replace("@FAMILIES@", &families.join("\n"))
+        .replace("@ACTIONS@", &actions.join("\n"))
+}
+
+struct Connection {
+    client: SocketAddrV4,
+    client_seq: u32,
+    server_seq: u32,
+}
+
+impl Connection {
+    fn new(session_id: u32, addr: Option<&str>) -> Self {
+        let client = match addr.and_then(|addr| addr.parse().ok()) {
+            Some(SocketAddr::V4(addr)) => addr,
+            // Made up for IPv6 clients, which can't be put in an IPv4 header
+            _ => SocketAddrV4::new(
+                Ipv4Addr::new(10, 1, (session_id >> 8) as u8, session_id as u8),
+                40000 + (session_id % 20000) as u16,
+            ),
+        };
+
+        Self {
+            client,
+            client_seq: 0,
+            server_seq: 0,
+        }
+    }
+
+    fn segment(&mut self, from_client: bool, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let (src, dst, seq, ack) = if from_client {
+            (self.client, SERVER_ADDR, self.client_seq, self.server_seq)
+        } else {
+            (SERVER_ADDR, self.client, self.server_seq, self.client_seq)
+        };
+
+        // SYN takes up a sequence number like a byte of data
+        let used = payload.len() as u32 + u32::from(flags & TCP_SYN != 0);
+        if from_client {
+            self.client_seq = self.client_seq.wrapping_add(used);
+        } else {
+            self.server_seq = self.server_seq.wrapping_add(used);
+        }
+
+        let ack = if flags & TCP_ACK != 0 { ack } else { 0 };
+        ipv4_tcp(src, dst, seq, ack, flags, payload)
+    }
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|chunk| u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn ipv4_tcp(
+    src: SocketAddrV4,
+    dst: SocketAddrV4,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut tcp = Vec::with_capacity(20 + payload.len());
+    tcp.extend_from_slice(&src.port().to_be_bytes());
+    tcp.extend_from_slice(&dst.port().to_be_bytes());
+    tcp.extend_from_slice(&seq.to_be_bytes());
+    tcp.extend_from_slice(&ack.to_be_bytes());
+    tcp.push(5 << 4);
+    tcp.push(flags);
+    tcp.extend_from_slice(&u16::MAX.to_be_bytes());
+    tcp.extend_from_slice(&[0, 0, 0, 0]);
+    tcp.extend_from_slice(payload);
+
+    let mut pseudo = Vec::with_capacity(12 + tcp.len());
+    pseudo.extend_from_slice(&src.ip().octets());
+    pseudo.extend_from_slice(&dst.ip().octets());
+    pseudo.extend_from_slice(&[0, 6]);
+    pseudo.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+    pseudo.extend_from_slice(&tcp);
+    tcp[16..18].copy_from_slice(&checksum(&pseudo).to_be_bytes());
+
+    let mut ip = Vec::with_capacity(20 + tcp.len());
+    ip.extend_from_slice(&[0x45, 0]);
+    ip.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+    ip.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+    ip.extend_from_slice(&src.ip().octets());
+    ip.extend_from_slice(&dst.ip().octets());
+    let header_checksum = checksum(&ip);
+    ip[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+    ip.append(&mut tcp);
+    ip
+}
+
+struct PcapngWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    fn new(writer: W) -> Result<Self> {
+        let mut pcapng = Self { writer };
+
+        let mut section = Vec::new();
+        section.extend_from_slice(&0x1A2B3C4Du32.to_le_bytes());
+        section.extend_from_slice(&1u16.to_le_bytes());
+        section.extend_from_slice(&0u16.to_le_bytes());
+        section.extend_from_slice(&(-1i64).to_le_bytes());
+        pcapng.block(SECTION_HEADER_BLOCK, &section)?;
+
+        // No options, so timestamps are in microseconds
+        let mut interface = Vec::new();
+        interface.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        interface.extend_from_slice(&0u16.to_le_bytes());
+        interface.extend_from_slice(&0u32.to_le_bytes());
+        pcapng.block(INTERFACE_DESCRIPTION_BLOCK, &interface)?;
+
+        Ok(pcapng)
+    }
+
+    fn block(&mut self, block_type: u32, body: &[u8]) -> Result<()> {
+        let padding = (4 - body.len() % 4) % 4;
+        let length = (12 + body.len() + padding) as u32;
+
+        self.writer.write_all(&block_type.to_le_bytes())?;
+        self.writer.write_all(&length.to_le_bytes())?;
+        self.writer.write_all(body)?;
+        self.writer.write_all(&[0; 3][..padding])?;
+        self.writer.write_all(&length.to_le_bytes())
+    }
+
+    fn packet(&mut self, time: DateTime<Local>, data: &[u8], comment: Option<&str>) -> Result<()> {
+        let micros = time.timestamp_micros() as u64;
+
+        let mut body = Vec::with_capacity(32 + data.len());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(micros as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        body.resize(body.len() + (4 - data.len() % 4) % 4, 0);
+
+        if let Some(mut comment) = comment {
+            // Options are at most u16::MAX bytes long, so longer comments are split between
+            // characters over several
+            while !comment.is_empty() {
+                let mut end = comment.len().min(u16::MAX as usize);
+                while !comment.is_char_boundary(end) {
+                    end -= 1;
+                }
+                let (chunk, rest) = comment.split_at(end);
+                comment = rest;
+
+                body.extend_from_slice(&OPTION_COMMENT.to_le_bytes());
+                body.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+                body.extend_from_slice(chunk.as_bytes());
+                body.resize(body.len() + (4 - chunk.len() % 4) % 4, 0);
+            }
+            // opt_endofopt
+            body.extend_from_slice(&[0; 4]);
+        }
+
+        self.block(ENHANCED_PACKET_BLOCK, &body)
+    }
+}
+
+fn comment(player_id: u32, from_client: bool, buf: &[u8]) -> String {
+    let mut comment = format!("player {} {}", player_id, decode::packet_name(buf));
+    if let Some(decoded) = decode::decode(from_client, buf) {
+        let mut fields = Vec::new();
+        flatten(String::new(), &decoded, &mut fields);
+        for (path, value) in fields {
+            comment.push_str(&format!("\n{}: {}", path, value));
+        }
+    }
+    comment
+}
+
+// The leaves of a decoded packet, as paths like coords.x and items[2].id
+fn flatten(path: String, value: &Value, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                flatten(path, field, out);
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                flatten(format!("{}[{}]", path, index), item, out);
+            }
+        }
+        leaf => out.push((path, leaf.to_string())),
+    }
+}
+
+pub fn pcapng(records: &[Record], path: &Path) -> Result<()> {
+    let mut pcapng = PcapngWriter::new(BufWriter::new(File::create(path)?))?;
+    let mut connections: HashMap<u32, Connection> = HashMap::new();
+
+    for record in records {
+        match record {
+            Record::Session {
+                time,
+                session_id,
+                addr,
+            } => {
+                let mut connection = Connection::new(*session_id, Some(addr));
+                pcapng.packet(*time, &connection.segment(true, TCP_SYN, &[]), None)?;
+                pcapng.packet(
+                    *time,
+                    &connection.segment(false, TCP_SYN | TCP_ACK, &[]),
+                    None,
+                )?;
+                pcapng.packet(*time, &connection.segment(true, TCP_ACK, &[]), None)?;
+                connections.insert(*session_id, connection);
+            }
+            Record::Packet {
+                time,
+                session_id,
+                player_id,
+                from_client,
+                buf,
+            } => {
+                let connection = connections
+                    .entry(*session_id)
+                    .or_insert_with(|| Connection::new(*session_id, None));
+
+                let length = encode_number(buf.len() as u32);
+                let mut payload = vec![length[0], length[1]];
+                payload.extend_from_slice(buf);
+
+                let segment = connection.segment(*from_client, TCP_PSH | TCP_ACK, &payload);
+                let comment = comment(*player_id, *from_client, buf);
+                pcapng.packet(*time, &segment, Some(&comment))?;
+            }
+        }
+    }
+
+    pcapng.writer.flush()
+}
+
+// The fields of WSMessage::Packet, plus when the packet was seen and what it decodes to
+#[derive(Serialize)]
+struct JsonPacket<'a> {
+    time: String,
+    session_id: u32,
+    player_id: u32,
+    from: &'static str,
+    family: &'a str,
+    action: &'a str,
+    buf: &'a [u8],
+    decoded: Option<Value>,
+}
+
+fn from(from_client: bool) -> &'static str {
+    if from_client {
+        "Client"
+    } else {
+        "Server"
+    }
+}
+
+fn time(time: &DateTime<Local>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, false)
+}
+
+pub fn jsonl(records: &[Record], path: &Path) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    for record in records {
+        if let Record::Packet {
+            time: packet_time,
+            session_id,
+            player_id,
+            from_client,
+            buf,
+        } = record
+        {
+            let name = decode::packet_name(buf);
+            let (family, action) = name.split_once('_').unwrap_or((&name, ""));
+
+            let packet = JsonPacket {
+                time: time(packet_time),
+                session_id: *session_id,
+                player_id: *player_id,
+                from: from(*from_client),
+                family,
+                action,
+                buf,
+                decoded: decode::decode(*from_client, buf),
+            };
+            serde_json::to_writer(&mut writer, &packet)?;
+            writer.write_all(b"\n")?;
+        }
+    }
+
+    writer.flush()
+}
+
+pub fn csv(records: &[Record], path: &Path) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "time,session,player,direction,family,action,length")?;
+
+    for record in records {
+        if let Record::Packet {
+            time: packet_time,
+            session_id,
+            player_id,
+            from_client,
+            buf,
+        } = record
+        {
+            let name = decode::packet_name(buf);
+            let (family, action) = name.split_once('_').unwrap_or((&name, ""));
+
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                time(packet_time),
+                session_id,
+                player_id,
+                from(*from_client),
+                family,
+                action,
+                buf.len()
+            )?;
+        }
+    }
+
+    writer.flush()
+}
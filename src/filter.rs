@@ -0,0 +1,569 @@
+use std::{cell::OnceCell, fmt};
+
+use serde_json::Value;
+
+use crate::decode;
+
+// A small expression language for picking packets out of the stream, e.g.
+//
+//   family == Walk && dir == client
+//   player == 12 && !(family in [Connection])
+//   decoded.message contains "gm"
+//
+// Fields are family, action, dir (client or server), player, len, and decoded.<path> for the
+// fields of the decoded protocol struct. Names are compared case-insensitively
+
+// Parentheses and negations nested deeper than this are refused rather than risking the stack
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Dot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Str(String),
+    Num(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    // Chains of && and || are kept flat so long filters don't nest
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    Compare(Vec<String>, Op, Literal),
+    In(Vec<String>, Vec<Literal>),
+    Exists(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid filter: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | '[' | ']' | ',' | '.' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    '[' => Token::LBracket,
+                    ']' => Token::RBracket,
+                    ',' => Token::Comma,
+                    _ => Token::Dot,
+                });
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let equals = chars.peek() == Some(&'=');
+                if equals {
+                    chars.next();
+                }
+                tokens.push(match (c, equals) {
+                    ('=', true) => Token::Eq,
+                    ('!', true) => Token::Ne,
+                    ('!', false) => Token::Not,
+                    ('<', true) => Token::Le,
+                    ('<', false) => Token::Lt,
+                    ('>', true) => Token::Ge,
+                    ('>', false) => Token::Gt,
+                    _ => return Err(ParseError("expected == after =".to_string())),
+                });
+            }
+            '&' | '|' => {
+                chars.next();
+                if chars.next() != Some(c) {
+                    return Err(ParseError(format!("expected {}{}", c, c)));
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => return Err(ParseError("unterminated string".to_string())),
+                        },
+                        Some(end) if end == c => break,
+                        Some(other) => value.push(other),
+                        None => return Err(ParseError("unterminated string".to_string())),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut number = String::new();
+                while let Some(&d) = chars.peek() {
+                    if d.is_ascii_digit() || (number.is_empty() && d == '-') {
+                        number.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Num(
+                    number
+                        .parse()
+                        .map_err(|_| ParseError(format!("bad number {}", number)))?,
+                ));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&d) = chars.peek() {
+                    if d.is_alphanumeric() || d == '_' {
+                        ident.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => return Err(ParseError(format!("unexpected character {}", c))),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(ParseError(format!(
+                "expected {:?}, found {:?}",
+                expected, token
+            ))),
+            None => Err(ParseError(format!("expected {:?}", expected))),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut terms = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            terms.push(self.and()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.pop().unwrap()
+        } else {
+            Expr::Or(terms)
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut terms = vec![self.unary()?];
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            terms.push(self.unary()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.pop().unwrap()
+        } else {
+            Expr::And(terms)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.next();
+                let expr = self.nested(Self::unary)?;
+                Ok(Expr::Not(Box::new(expr)))
+            }
+            Some(Token::LParen) => {
+                self.next();
+                let expr = self.nested(Self::or)?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Expr, ParseError>,
+    ) -> Result<Expr, ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(ParseError("nested too deeply".to_string()));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn field(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut path = Vec::new();
+        loop {
+            match self.next() {
+                Some(Token::Ident(name)) => path.push(name.to_lowercase()),
+                Some(Token::Num(index)) if !path.is_empty() => path.push(index.to_string()),
+                other => return Err(ParseError(format!("expected field, found {:?}", other))),
+            }
+            if self.peek() != Some(&Token::Dot) {
+                return Ok(path);
+            }
+            self.next();
+        }
+    }
+
+    fn literal(&mut self) -> Result<Literal, ParseError> {
+        match self.next() {
+            Some(Token::Str(value)) | Some(Token::Ident(value)) => Ok(Literal::Str(value)),
+            Some(Token::Num(value)) => Ok(Literal::Num(value)),
+            other => Err(ParseError(format!("expected value, found {:?}", other))),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let field = self.field()?;
+
+        let op = match self.peek() {
+            Some(Token::Eq) => Op::Eq,
+            Some(Token::Ne) => Op::Ne,
+            Some(Token::Lt) => Op::Lt,
+            Some(Token::Le) => Op::Le,
+            Some(Token::Gt) => Op::Gt,
+            Some(Token::Ge) => Op::Ge,
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("contains") => Op::Contains,
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("in") => {
+                self.next();
+                self.expect(Token::LBracket)?;
+                let mut values = Vec::new();
+                if self.peek() != Some(&Token::RBracket) {
+                    values.push(self.literal()?);
+                    while self.peek() == Some(&Token::Comma) {
+                        self.next();
+                        values.push(self.literal()?);
+                    }
+                }
+                self.expect(Token::RBracket)?;
+                return Ok(Expr::In(field, values));
+            }
+            _ => return Ok(Expr::Exists(field)),
+        };
+
+        self.next();
+        Ok(Expr::Compare(field, op, self.literal()?))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        };
+
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(ParseError(format!("unexpected {:?}", token)));
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    pub fn matches(&self, packet: &PacketContext) -> bool {
+        evaluate(&self.expr, packet)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+// Everything a filter can look at. Decoding is only done if the filter asks for it
+pub struct PacketContext<'a> {
+    pub player_id: u32,
+    pub from_client: bool,
+    pub buf: &'a [u8],
+    decoded: OnceCell<Option<Value>>,
+}
+
+impl<'a> PacketContext<'a> {
+    pub fn new(player_id: u32, from_client: bool, buf: &'a [u8]) -> Self {
+        Self {
+            player_id,
+            from_client,
+            buf,
+            decoded: OnceCell::new(),
+        }
+    }
+
+    fn decoded(&self) -> Option<&Value> {
+        self.decoded
+            .get_or_init(|| decode::decode(self.from_client, self.buf))
+            .as_ref()
+    }
+
+    fn field(&self, path: &[String]) -> Option<Literal> {
+        let name = decode::packet_name(self.buf);
+        let (family, action) = name.split_once('_').unwrap_or((&name, ""));
+
+        match path.first().map(String::as_str) {
+            Some("family") => Some(Literal::Str(family.to_string())),
+            Some("action") => Some(Literal::Str(action.to_string())),
+            Some("id") => Some(Literal::Str(name.clone())),
+            Some("dir") => Some(Literal::Str(
+                if self.from_client { "client" } else { "server" }.to_string(),
+            )),
+            Some("player") => Some(Literal::Num(self.player_id as f64)),
+            Some("len") => Some(Literal::Num(self.buf.len() as f64)),
+            Some("decoded") => {
+                let mut value = self.decoded()?;
+                for key in &path[1..] {
+                    value = match value {
+                        Value::Object(map) => map
+                            .iter()
+                            .find(|(k, _)| k.to_lowercase() == *key)
+                            .map(|(_, v)| v)?,
+                        Value::Array(items) => items.get(key.parse::<usize>().ok()?)?,
+                        _ => return None,
+                    };
+                }
+                Some(match value {
+                    Value::Number(n) => Literal::Num(n.as_f64()?),
+                    Value::String(s) => Literal::Str(s.clone()),
+                    Value::Null => return None,
+                    other => Literal::Str(other.to_string()),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn compare(left: &Literal, op: Op, right: &Literal) -> bool {
+    match (left, right) {
+        (Literal::Num(l), Literal::Num(r)) => match op {
+            Op::Eq => l == r,
+            Op::Ne => l != r,
+            Op::Lt => l < r,
+            Op::Le => l <= r,
+            Op::Gt => l > r,
+            Op::Ge => l >= r,
+            Op::Contains => false,
+        },
+        _ => {
+            let l = literal_string(left).to_lowercase();
+            let r = literal_string(right).to_lowercase();
+            match op {
+                Op::Eq => l == r,
+                Op::Ne => l != r,
+                Op::Lt => l < r,
+                Op::Le => l <= r,
+                Op::Gt => l > r,
+                Op::Ge => l >= r,
+                Op::Contains => l.contains(&r),
+            }
+        }
+    }
+}
+
+fn literal_string(literal: &Literal) -> String {
+    match literal {
+        Literal::Str(s) => s.clone(),
+        Literal::Num(n) => n.to_string(),
+    }
+}
+
+fn evaluate(expr: &Expr, packet: &PacketContext) -> bool {
+    match expr {
+        Expr::And(terms) => terms.iter().all(|term| evaluate(term, packet)),
+        Expr::Or(terms) => terms.iter().any(|term| evaluate(term, packet)),
+        Expr::Not(inner) => !evaluate(inner, packet),
+        Expr::Exists(path) => packet.field(path).is_some(),
+        Expr::Compare(path, op, value) => match packet.field(path) {
+            Some(field) => compare(&field, *op, value),
+            // A missing field is never equal to anything
+            None => *op == Op::Ne,
+        },
+        Expr::In(path, values) => match packet.field(path) {
+            Some(field) => values.iter().any(|value| compare(&field, Op::Eq, value)),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eo::protocol::{PacketAction, PacketFamily};
+
+    use super::*;
+
+    fn compare(field: &str, value: &str) -> Expr {
+        Expr::Compare(
+            vec![field.to_string()],
+            Op::Eq,
+            Literal::Str(value.to_string()),
+        )
+    }
+
+    fn parse(source: &str) -> Result<Expr, ParseError> {
+        Filter::parse(source).map(|filter| filter.expr)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("family == Walk || family == Face && dir == client"),
+            Ok(Expr::Or(vec![
+                compare("family", "Walk"),
+                Expr::And(vec![compare("family", "Face"), compare("dir", "client")]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        assert_eq!(
+            parse("(family == Walk || family == Face) && dir == client"),
+            Ok(Expr::And(vec![
+                Expr::Or(vec![compare("family", "Walk"), compare("family", "Face")]),
+                compare("dir", "client"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn not_applies_to_the_next_term() {
+        assert_eq!(
+            parse("!family == Walk && dir == client"),
+            Ok(Expr::And(vec![
+                Expr::Not(Box::new(compare("family", "Walk"))),
+                compare("dir", "client"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn chains_stay_flat() {
+        let source = vec!["len > 1"; 1000].join(" || ");
+        match parse(&source) {
+            Ok(Expr::Or(terms)) => assert_eq!(terms.len(), 1000),
+            other => panic!("expected a flat or, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_refused() {
+        let nested = format!("{}len > 1{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(parse(&nested).is_ok());
+
+        let too_deep = format!(
+            "{}len > 1{}",
+            "(".repeat(MAX_DEPTH + 1),
+            ")".repeat(MAX_DEPTH + 1)
+        );
+        assert!(parse(&too_deep).is_err());
+        assert!(parse(&"!".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn malformed_filters_are_errors() {
+        for source in [
+            "",
+            "family ==",
+            "family = Walk",
+            "(family == Walk",
+            "family == Walk)",
+            "family == Walk &&",
+            "family & Walk",
+            "player in [1, 2",
+            "message == \"unterminated",
+            "family == Walk family",
+            "#",
+        ] {
+            assert!(parse(source).is_err(), "{:?} should not parse", source);
+        }
+    }
+
+    #[test]
+    fn matches_packets() {
+        let walk = [
+            PacketAction::Player.to_byte(),
+            PacketFamily::Walk.to_byte(),
+            0,
+        ];
+        let filter = Filter::parse("family == walk && dir == client && player == 12").unwrap();
+        assert!(filter.matches(&PacketContext::new(12, true, &walk)));
+        assert!(!filter.matches(&PacketContext::new(12, false, &walk)));
+        assert!(!filter.matches(&PacketContext::new(13, true, &walk)));
+    }
+}
@@ -0,0 +1,273 @@
+use std::{collections::VecDeque, net::SocketAddr, path::PathBuf};
+
+use chrono::{DateTime, Local};
+use eo::protocol::{PacketAction, PacketFamily};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde_json::Value;
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    capture::{CaptureWriter, Record},
+    decode,
+    settings::Fuzz,
+    PacketBuf,
+};
+
+// Once a session has entered the game, client packets are mutated at the configured rate
+// before being sent on. If the server then drops the connection or goes quiet for
+// hang_timeout_ms, the session's latest packets are saved as a capture
+
+// Edges of the EO number types: char, short, three and int
+const BOUNDARY_NUMBERS: &[u64] = &[0, 1, 252, 253, 64008, 64009, 16194276, 16194277, 4097152080];
+
+// Bytes EO treats specially: 0 and 254 decode oddly as numbers and 255 breaks strings
+const INVALID_BYTES: &[u8] = &[0, 254, 255];
+
+// Packets kept for the capture. A crash is almost always down to the last few
+const MAX_HISTORY: usize = 10_000;
+
+pub struct Fuzzer {
+    rng: StdRng,
+    rate: f64,
+    hang_timeout: Duration,
+    directory: PathBuf,
+    session_id: u32,
+    in_game: bool,
+    addr: SocketAddr,
+    started: DateTime<Local>,
+    history: VecDeque<Record>,
+    // Packets seen, including those no longer in history
+    packets: usize,
+    mutations: Vec<String>,
+    awaiting_reply: Option<Instant>,
+}
+
+impl Fuzzer {
+    pub fn for_session(settings: &Fuzz, session_id: u32, addr: SocketAddr) -> Option<Self> {
+        if !settings.enabled {
+            return None;
+        }
+
+        // Each session gets its own seed so runs can be repeated
+        let seed = settings
+            .seed
+            .map_or_else(rand::random, |seed| seed.wrapping_add(session_id.into()));
+        info!("Fuzzing with seed {}", seed);
+
+        Some(Self {
+            rng: StdRng::seed_from_u64(seed),
+            rate: settings.rate,
+            hang_timeout: Duration::from_millis(settings.hang_timeout_ms),
+            directory: PathBuf::from(&settings.directory),
+            session_id,
+            in_game: false,
+            addr,
+            started: Local::now(),
+            history: VecDeque::new(),
+            packets: 0,
+            mutations: Vec::new(),
+            awaiting_reply: None,
+        })
+    }
+
+    fn record(&mut self, player_id: u32, from_client: bool, buf: &[u8]) {
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(Record::Packet {
+            time: Local::now(),
+            session_id: self.session_id,
+            player_id,
+            from_client,
+            buf: buf.to_vec(),
+        });
+        self.packets += 1;
+    }
+
+    // Returns the packet to send in place of buf
+    pub fn client_packet(&mut self, player_id: u32, buf: PacketBuf) -> PacketBuf {
+        let entering_game = buf.len() >= 2
+            && buf[0] == PacketAction::Msg.to_byte()
+            && buf[1] == PacketFamily::Welcome.to_byte();
+
+        let buf = if self.in_game && buf.len() >= 2 && self.rng.gen_bool(self.rate) {
+            let (mutated, description) = self.mutate(&buf);
+            debug!("Fuzzing {}: {}", decode::packet_name(&buf), description);
+            self.mutations.push(format!(
+                "packet {} {}: {}",
+                self.packets + 1,
+                decode::packet_name(&buf),
+                description
+            ));
+            self.awaiting_reply = Some(Instant::now() + self.hang_timeout);
+            mutated
+        } else {
+            buf
+        };
+
+        if entering_game {
+            self.in_game = true;
+        }
+
+        self.record(player_id, true, &buf);
+        buf
+    }
+
+    pub fn server_packet(&mut self, player_id: u32, buf: &[u8]) {
+        self.awaiting_reply = None;
+        self.record(player_id, false, buf);
+    }
+
+    // The server counts as hung if it sends nothing between a mutation and this
+    pub fn deadline(&self) -> Option<Instant> {
+        self.awaiting_reply
+    }
+
+    pub fn hang(&mut self) {
+        self.awaiting_reply = None;
+        self.save("hang");
+    }
+
+    pub fn server_closed(&mut self) {
+        if !self.mutations.is_empty() {
+            self.save("disconnect");
+        }
+    }
+
+    fn save(&self, kind: &str) {
+        let name = format!(
+            "{}-{}-{}",
+            Local::now().format("%Y%m%d-%H%M%S"),
+            self.session_id,
+            kind
+        );
+        let capture = self.directory.join(format!("{}.cap", name));
+        let mutations = self.directory.join(format!("{}.txt", name));
+
+        let saved = std::fs::create_dir_all(&self.directory)
+            .and_then(|_| CaptureWriter::create(&capture, self.session_id, None))
+            .and_then(|mut writer| {
+                writer.write(&Record::Session {
+                    time: self.started,
+                    session_id: self.session_id,
+                    addr: self.addr.to_string(),
+                })?;
+                self.history
+                    .iter()
+                    .try_for_each(|record| writer.write(record))
+            })
+            .and_then(|_| std::fs::write(&mutations, self.mutations.join("\n")));
+
+        match saved {
+            Ok(()) => warn!(
+                "Server {} after {}, saved to {:?}",
+                kind,
+                self.mutations.last().map_or("", String::as_str),
+                capture
+            ),
+            Err(e) => error!("Failed to save fuzzing {} to {:?}: {}", kind, capture, e),
+        }
+    }
+
+    fn mutate(&mut self, buf: &[u8]) -> (PacketBuf, String) {
+        // The sequence byte is left alone so the server doesn't just drop the connection
+        let header = match PacketFamily::from_byte(buf[1]) {
+            Some(family) if decode::has_sequence(true, family) => 3.min(buf.len()),
+            _ => 2,
+        };
+
+        if self.rng.gen_bool(0.5) {
+            if let Some(mutated) = self.mutate_field(buf) {
+                return mutated;
+            }
+        }
+
+        let mut mutated = buf.to_vec();
+        match self.rng.gen_range(0..4) {
+            0 if mutated.len() > header => {
+                let length = self.rng.gen_range(header..mutated.len());
+                mutated.truncate(length);
+                (mutated, format!("truncated to {} bytes", length))
+            }
+            1 => {
+                let extra = self.rng.gen_range(1..=64);
+                mutated.extend((0..extra).map(|_| self.rng.gen_range(1..=253u8)));
+                (mutated, format!("padded with {} bytes", extra))
+            }
+            2 => {
+                mutated[0] = loop {
+                    let action = self.rng.gen();
+                    if PacketAction::from_byte(action).is_none() {
+                        break action;
+                    }
+                };
+                let description = format!("action set to {}", mutated[0]);
+                (mutated, description)
+            }
+            _ if mutated.len() > header => {
+                let index = self.rng.gen_range(header..mutated.len());
+                mutated[index] = *INVALID_BYTES.choose(&mut self.rng).unwrap();
+                let description = format!("byte {} set to {}", index, mutated[index]);
+                (mutated, description)
+            }
+            _ => {
+                mutated.push(*INVALID_BYTES.choose(&mut self.rng).unwrap());
+                (mutated, "appended an invalid byte".to_string())
+            }
+        }
+    }
+
+    // Sets one field of the decoded packet to an edge case, when the packet can be decoded
+    fn mutate_field(&mut self, buf: &[u8]) -> Option<(PacketBuf, String)> {
+        let action = PacketAction::from_byte(buf[0])?;
+        let family = PacketFamily::from_byte(buf[1])?;
+        let value = decode::decode(true, buf)?;
+
+        let mut fields = Vec::new();
+        leaves(String::new(), &value, &mut fields);
+        let (pointer, is_string) = fields.choose(&mut self.rng)?;
+
+        let mut candidates: Vec<Value> = if *is_string {
+            vec![
+                Value::from(""),
+                Value::from("A".repeat(1000)),
+                Value::from("\u{ff}"),
+            ]
+        } else {
+            BOUNDARY_NUMBERS.iter().map(|&n| Value::from(n)).collect()
+        };
+        candidates.shuffle(&mut self.rng);
+
+        // Values too big for the field's type won't encode, so try until one does
+        for candidate in candidates {
+            let description = format!("{} set to {}", pointer, candidate);
+            let mut mutated = value.clone();
+            *mutated.pointer_mut(pointer)? = candidate;
+
+            if let Some(buf) = decode::encode(true, family, action, buf.get(2).copied(), mutated) {
+                return Some((buf, description));
+            }
+        }
+
+        None
+    }
+}
+
+// JSON pointers to the numbers and strings in a decoded packet
+fn leaves(pointer: String, value: &Value, out: &mut Vec<(String, bool)>) {
+    match value {
+        Value::Number(_) => out.push((pointer, false)),
+        Value::String(_) => out.push((pointer, true)),
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                leaves(format!("{}/{}", pointer, key), field, out);
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                leaves(format!("{}/{}", pointer, index), item, out);
+            }
+        }
+        _ => {}
+    }
+}
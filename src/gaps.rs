@@ -0,0 +1,214 @@
+use std::{cmp::Reverse, collections::HashMap, fs, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Local};
+use eo::protocol::{PacketAction, PacketFamily};
+use lazy_static::lazy_static;
+
+use crate::{decode, PacketBuf, SETTINGS};
+
+// Collects packets the protocol definitions don't cover: ids eo doesn't know, and payloads
+// that don't deserialize cleanly into their eo type. Written out as a JSON report
+
+lazy_static! {
+    static ref GAPS: Mutex<Gaps> = Mutex::new(Gaps::default());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    // Fewer than the two bytes of action and family
+    TooShort,
+    UnknownFamily,
+    UnknownAction,
+    // Known ids eo has no type for in this direction
+    NoType,
+    // Client packets past Init without their sequence byte
+    NoSequence,
+    // The eo type stopped reading before the end of the payload
+    LeftoverBytes,
+    // The eo type wanted more bytes than the payload had
+    ShortRead,
+    // The eo type read everything, but serializes to different bytes
+    Mismatch,
+}
+
+#[derive(Debug, Serialize)]
+struct Gap {
+    from: &'static str,
+    action: Option<u8>,
+    family: Option<u8>,
+    name: String,
+    problem: Problem,
+    detail: String,
+    count: u64,
+    first_seen: DateTime<Local>,
+    last_seen: DateTime<Local>,
+    samples: Vec<PacketBuf>,
+}
+
+#[derive(Default)]
+struct Gaps {
+    gaps: HashMap<(bool, Option<u8>, Option<u8>, Problem), Gap>,
+    changed: bool,
+}
+
+fn find_problem(from_client: bool, buf: &[u8]) -> Option<(Problem, String)> {
+    if buf.len() < 2 {
+        return Some((Problem::TooShort, format!("{} bytes", buf.len())));
+    }
+    if PacketFamily::from_byte(buf[1]).is_none() {
+        return Some((Problem::UnknownFamily, format!("family {}", buf[1])));
+    }
+    if PacketAction::from_byte(buf[0]).is_none() {
+        return Some((Problem::UnknownAction, format!("action {}", buf[0])));
+    }
+
+    let family = PacketFamily::from_byte(buf[1])?;
+    if decode::has_sequence(from_client, family) && buf.len() < 3 {
+        return Some((Problem::NoSequence, format!("{} bytes", buf.len())));
+    }
+
+    let (payload, remaining, reencoded) = match decode::round_trip(from_client, buf) {
+        Some(round_trip) => round_trip,
+        None => return Some((Problem::NoType, decode::packet_name(buf))),
+    };
+    let reencoded = match reencoded {
+        Some(reencoded) => reencoded,
+        None => {
+            return Some((
+                Problem::ShortRead,
+                format!("{} bytes, read past the end", payload.len()),
+            ))
+        }
+    };
+    if remaining > 0 {
+        Some((
+            Problem::LeftoverBytes,
+            format!("{} of {} bytes not read", remaining, payload.len()),
+        ))
+    } else if reencoded.len() > payload.len() {
+        Some((
+            Problem::ShortRead,
+            format!("{} bytes, expected {}", payload.len(), reencoded.len()),
+        ))
+    } else if reencoded != payload {
+        Some((Problem::Mismatch, format!("re-encoded as {:?}", reencoded)))
+    } else {
+        None
+    }
+}
+
+pub fn enabled() -> bool {
+    SETTINGS.protocol_gaps.enabled
+}
+
+pub fn check(from_client: bool, buf: &[u8]) {
+    let (problem, detail) = match find_problem(from_client, buf) {
+        Some(problem) => problem,
+        None => return,
+    };
+
+    let action = buf.first().copied();
+    let family = buf.get(1).copied();
+    let now = Local::now();
+
+    let mut gaps = GAPS.lock().unwrap();
+    gaps.changed = true;
+    let gap = gaps
+        .gaps
+        .entry((from_client, action, family, problem))
+        .or_insert_with(|| {
+            warn!(
+                "Protocol gap: {} {} {:?} ({})",
+                if from_client { "client" } else { "server" },
+                decode::packet_name(buf),
+                problem,
+                detail
+            );
+            Gap {
+                from: if from_client { "Client" } else { "Server" },
+                action,
+                family,
+                name: decode::packet_name(buf),
+                problem,
+                detail: String::new(),
+                count: 0,
+                first_seen: now,
+                last_seen: now,
+                samples: Vec::new(),
+            }
+        });
+
+    gap.count += 1;
+    gap.last_seen = now;
+    gap.detail = detail;
+    if gap.samples.len() < SETTINGS.protocol_gaps.samples {
+        gap.samples.push(buf.to_vec());
+    }
+}
+
+fn write_report(path: &str) {
+    let json = {
+        let mut gaps = GAPS.lock().unwrap();
+        if !gaps.changed {
+            return;
+        }
+        gaps.changed = false;
+
+        let mut report: Vec<&Gap> = gaps.gaps.values().collect();
+        report.sort_by_key(|gap| Reverse(gap.count));
+        serde_json::to_string_pretty(&report)
+    };
+
+    let written = json
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        error!("Failed to write protocol gaps report {}: {}", path, e);
+    }
+}
+
+// Rewrites the report whenever new gaps have been seen
+pub async fn watch() {
+    let settings = &SETTINGS.protocol_gaps;
+    let mut interval = tokio::time::interval(Duration::from_secs(settings.interval_secs));
+
+    loop {
+        interval.tick().await;
+        write_report(&settings.report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_packets_eo_cannot_read() {
+        let walk_reply = [
+            PacketAction::Reply.to_byte(),
+            PacketFamily::Walk.to_byte(),
+            5,
+        ];
+        assert_eq!(
+            find_problem(true, &walk_reply).map(|(problem, _)| problem),
+            Some(Problem::NoType)
+        );
+
+        let account_create = [
+            PacketAction::Create.to_byte(),
+            PacketFamily::Account.to_byte(),
+            5,
+        ];
+        assert_eq!(
+            find_problem(true, &account_create).map(|(problem, _)| problem),
+            Some(Problem::ShortRead)
+        );
+
+        let no_sequence = [PacketAction::Player.to_byte(), PacketFamily::Walk.to_byte()];
+        assert_eq!(
+            find_problem(true, &no_sequence).map(|(problem, _)| problem),
+            Some(Problem::NoSequence)
+        );
+    }
+}
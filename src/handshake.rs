@@ -0,0 +1,97 @@
+use eo::{
+    data::{EOByte, EOChar, EOShort, EOThree, Serializeable, StreamReader},
+    protocol::{client, server::init::InitData},
+};
+
+// What the client and server agreed on during the Init exchange. The client's Init_Init
+// carries a challenge, its version and its HDID, and the server answers with the multiples
+// and sequence start for the session, or with why it won't let the client in
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    OutOfDate { required_version: String },
+    Banned { ban: String },
+    Other { reply: String },
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Handshake {
+    pub challenge: Option<EOThree>,
+    pub version: Option<String>,
+    // The version passed on to the server, when a version mapping rewrote it
+    pub rewritten_version: Option<String>,
+    pub hdid: Option<String>,
+    pub encode_multiple: Option<EOByte>,
+    pub decode_multiple: Option<EOByte>,
+    pub sequence_start: Option<i32>,
+    // None if the client's challenge wasn't seen
+    pub challenge_verified: Option<bool>,
+    pub outcome: Option<Outcome>,
+}
+
+// The answer a genuine server gives to the client's challenge
+pub fn server_verification_hash(challenge: EOThree) -> EOThree {
+    let challenge = challenge as i64 + 1;
+    let hash = 110905
+        + (challenge % 9 + 1) * ((11092004 - challenge) % ((challenge % 11 + 1) * 119)) * 119
+        + challenge % 2004;
+    hash as EOThree
+}
+
+fn format_version([major, minor, patch]: [EOChar; 3]) -> String {
+    format!("{}.{}.{}", major, minor, patch)
+}
+
+impl Handshake {
+    pub fn complete(&self) -> bool {
+        self.outcome.is_some()
+    }
+
+    pub fn client_init(&mut self, payload: &[u8]) {
+        let mut init = client::init::Init::new();
+        init.deserialize(&StreamReader::new(payload));
+
+        self.challenge = Some(init.challenge);
+        self.version = Some(format_version(init.version));
+        self.hdid = Some(init.hdid);
+    }
+
+    // Returns the player id the server gave out, if it let the client in
+    pub fn server_init(&mut self, data: &InitData) -> Option<EOShort> {
+        let (outcome, player_id) = match data {
+            InitData::Ok(ok) => {
+                self.encode_multiple = Some(ok.encode_multiple);
+                self.decode_multiple = Some(ok.decode_multiple);
+                let [seq1, seq2] = ok.seq_bytes;
+                self.sequence_start = Some(seq1 as i32 * 7 + seq2 as i32 - 13);
+                self.challenge_verified = self
+                    .challenge
+                    .map(|challenge| server_verification_hash(challenge) == ok.response);
+                (Outcome::Ok, Some(ok.player_id))
+            }
+            InitData::OutOfDate(out_of_date) => (
+                Outcome::OutOfDate {
+                    required_version: format_version(out_of_date.version),
+                },
+                None,
+            ),
+            InitData::Banned(banned) => (
+                Outcome::Banned {
+                    ban: format!("{:?}", banned),
+                },
+                None,
+            ),
+            data => (
+                Outcome::Other {
+                    reply: format!("{:?}", data),
+                },
+                None,
+            ),
+        };
+
+        self.outcome = Some(outcome);
+        player_id
+    }
+}
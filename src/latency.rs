@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Local};
+use eo::protocol::{PacketAction, PacketFamily};
+
+// Requests the other side is expected to answer, and the packet that answers them.
+// Only replies sent to the requester alone count, a broadcast like Talk_Player could be
+// answering someone else entirely
+fn reply_for(
+    from_client: bool,
+    family: PacketFamily,
+    action: PacketAction,
+) -> Option<(PacketFamily, PacketAction)> {
+    match (from_client, family, action) {
+        (true, PacketFamily::Walk, PacketAction::Player) => {
+            Some((PacketFamily::Walk, PacketAction::Reply))
+        }
+        // The server pings the client, so this is the round trip between the proxy and the
+        // player rather than the server
+        (false, PacketFamily::Connection, PacketAction::Player) => {
+            Some((PacketFamily::Connection, PacketAction::Ping))
+        }
+        _ => None,
+    }
+}
+
+fn is_ping(from_client: bool, family: PacketFamily, action: PacketAction) -> bool {
+    !from_client && family == PacketFamily::Connection && action == PacketAction::Player
+}
+
+// Requests that never get answered shouldn't pile up forever
+const MAX_PENDING: usize = 64;
+
+struct PendingRequest {
+    from_client: bool,
+    family: PacketFamily,
+    action: PacketAction,
+    reply: (PacketFamily, PacketAction),
+    sent_at: DateTime<Local>,
+}
+
+#[derive(Debug)]
+pub struct Measurement {
+    pub request: String,
+    pub response: Duration,
+    pub client_rtt: Option<Duration>,
+}
+
+pub struct LatencyTracker {
+    pending: VecDeque<PendingRequest>,
+    client_rtt: Option<Duration>,
+    relay_total: Duration,
+    relay_count: i32,
+    response_total: Duration,
+    response_count: i32,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            client_rtt: None,
+            relay_total: Duration::zero(),
+            relay_count: 0,
+            response_total: Duration::zero(),
+            response_count: 0,
+        }
+    }
+
+    // Time between a packet arriving on one bus and leaving on the other
+    pub fn relayed(&mut self, received_at: DateTime<Local>, sent_at: DateTime<Local>) {
+        self.relay_total += sent_at - received_at;
+        self.relay_count += 1;
+    }
+
+    pub fn request_sent(
+        &mut self,
+        from_client: bool,
+        family: PacketFamily,
+        action: PacketAction,
+        sent_at: DateTime<Local>,
+    ) {
+        if let Some(reply) = reply_for(from_client, family, action) {
+            if self.pending.len() == MAX_PENDING {
+                self.pending.pop_front();
+            }
+
+            self.pending.push_back(PendingRequest {
+                from_client,
+                family,
+                action,
+                reply,
+                sent_at,
+            });
+        }
+    }
+
+    pub fn reply_received(
+        &mut self,
+        from_client: bool,
+        family: PacketFamily,
+        action: PacketAction,
+        received_at: DateTime<Local>,
+    ) -> Option<Measurement> {
+        let index = self
+            .pending
+            .iter()
+            .position(|p| p.from_client != from_client && p.reply == (family, action))?;
+        let request = self.pending.remove(index)?;
+        let response = received_at - request.sent_at;
+
+        if is_ping(request.from_client, request.family, request.action) {
+            self.client_rtt = Some(response);
+        } else {
+            self.response_total += response;
+            self.response_count += 1;
+        }
+
+        Some(Measurement {
+            request: format!("{:?}_{:?}", request.family, request.action),
+            response,
+            client_rtt: self.client_rtt,
+        })
+    }
+
+    pub fn average_relay(&self) -> Duration {
+        if self.relay_count == 0 {
+            Duration::zero()
+        } else {
+            self.relay_total / self.relay_count
+        }
+    }
+
+    pub fn average_response(&self) -> Duration {
+        if self.response_count == 0 {
+            Duration::zero()
+        } else {
+            self.response_total / self.response_count
+        }
+    }
+
+    pub fn client_rtt(&self) -> Option<Duration> {
+        self.client_rtt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(ms: i64) -> DateTime<Local> {
+        Local.timestamp_millis_opt(ms).unwrap()
+    }
+
+    #[test]
+    fn times_the_server_ping_until_the_client_answers() {
+        let mut latency = LatencyTracker::new();
+
+        // Only the client's Connection_Ping answers the server's ping
+        latency.request_sent(
+            false,
+            PacketFamily::Connection,
+            PacketAction::Player,
+            at(1000),
+        );
+        assert!(latency
+            .reply_received(
+                true,
+                PacketFamily::Connection,
+                PacketAction::Accept,
+                at(1010)
+            )
+            .is_none());
+        let ping = latency
+            .reply_received(true, PacketFamily::Connection, PacketAction::Ping, at(1040))
+            .unwrap();
+        assert_eq!(ping.request, "Connection_Player");
+        assert_eq!(ping.response, Duration::milliseconds(40));
+        assert_eq!(latency.client_rtt(), Some(Duration::milliseconds(40)));
+        assert_eq!(latency.average_response(), Duration::zero());
+
+        latency.request_sent(true, PacketFamily::Walk, PacketAction::Player, at(2000));
+        // A Connection_Ping from the server isn't the client's answer
+        assert!(latency
+            .reply_received(
+                false,
+                PacketFamily::Connection,
+                PacketAction::Ping,
+                at(2005)
+            )
+            .is_none());
+        let walk = latency
+            .reply_received(false, PacketFamily::Walk, PacketAction::Reply, at(2030))
+            .unwrap();
+        assert_eq!(walk.request, "Walk_Player");
+        assert_eq!(walk.response, Duration::milliseconds(30));
+        assert_eq!(walk.client_rtt, Some(Duration::milliseconds(40)));
+        assert_eq!(latency.average_response(), Duration::milliseconds(30));
+    }
+}
@@ -0,0 +1,361 @@
+const VERSION: &str = "0.0.0";
+
+#[macro_use]
+extern crate tracing;
+#[macro_use]
+extern crate serde_derive;
+
+use std::{
+    net::SocketAddr,
+    process::ExitCode,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use eo::data::{EOByte, Serializeable, StreamBuilder};
+use lazy_static::lazy_static;
+
+pub type PacketBuf = Vec<EOByte>;
+
+fn serialize<T: Serializeable>(packet: &T) -> PacketBuf {
+    let mut builder = StreamBuilder::new();
+    packet.serialize(&mut builder);
+    builder.get()
+}
+
+mod settings;
+use settings::Settings;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+    time::timeout,
+};
+use tracing::{field, Instrument};
+
+// mod player;
+// use player::Player;
+
+mod access;
+mod bans;
+pub mod bot;
+mod breakpoint;
+mod bus;
+use bus::Stream;
+mod capture;
+mod chat;
+pub mod cli;
+use cli::{Cli, Command};
+mod decode;
+mod diff;
+mod export;
+mod filter;
+mod fuzz;
+mod gaps;
+mod handshake;
+mod latency;
+mod load;
+mod logging;
+pub mod mock;
+mod monitor;
+mod proxy_protocol;
+mod rate_limit;
+mod replies;
+mod scripting;
+mod session;
+mod tls;
+mod tracking;
+mod tui;
+mod versions;
+mod websocket;
+
+lazy_static! {
+    static ref SETTINGS: Settings = Settings::new().expect("Failed to load settings!");
+}
+
+static NEXT_SESSION_ID: AtomicU32 = AtomicU32::new(1);
+
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const WEBSOCKET_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, serde_derive::Serialize)]
+enum WSMessage {
+    AddPlayer {
+        session_id: u32,
+    },
+    RemovePlayer {
+        session_id: u32,
+        player_id: u32,
+    },
+    SetPlayerId {
+        session_id: u32,
+        player_id: u32,
+    },
+    Packet {
+        session_id: u32,
+        player_id: u32,
+        from: String,
+        buf: Vec<u8>,
+    },
+    Latency {
+        session_id: u32,
+        player_id: u32,
+        request: String,
+        response_ms: i64,
+        client_rtt_ms: Option<i64>,
+        average_relay_us: i64,
+    },
+    RateLimited {
+        session_id: u32,
+        player_id: u32,
+        family: String,
+        action: String,
+        verdict: String,
+    },
+    ConnectionRejected {
+        addr: String,
+        reason: String,
+    },
+    PlayerInfo {
+        session_id: u32,
+        player_id: u32,
+        addr: String,
+        character: Option<String>,
+    },
+    Breakpoint {
+        session_id: u32,
+        player_id: u32,
+        from: String,
+        buf: Vec<u8>,
+    },
+    Handshake {
+        session_id: u32,
+        player_id: u32,
+        handshake: handshake::Handshake,
+    },
+    Chat(chat::ChatMessage),
+}
+
+pub async fn run(cli: Cli) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    if let Some(command) = &cli.command {
+        return match command {
+            Command::Export(args) => export::run(args).map(|()| ExitCode::SUCCESS),
+            // Lets scripts treat any difference as a failed run
+            Command::Diff(args) => diff::run(args).map(|differ| {
+                if differ {
+                    ExitCode::FAILURE
+                } else {
+                    ExitCode::SUCCESS
+                }
+            }),
+            Command::Bot(args) => bot::run(args).await.map(|()| ExitCode::SUCCESS),
+            Command::Load(args) => load::run(args).await.map(|()| ExitCode::SUCCESS),
+        };
+    }
+
+    let _log_guard = logging::init(&SETTINGS.log, !cli.tui);
+    if !cli.tui {
+        println!(
+            "'||''''|   ..|''||   '||''|.
+||  .    .|'    ||   ||   || ... ..    ...   ... ... .... ...
+||''|    ||      ||  ||...|'  ||' '' .|  '|.  '|..'   '|.  |
+||       '|.     ||  ||       ||     ||   ||   .|.     '|.|
+.||.....|  ''|...|'  .||.     .||.     '|..|' .|  ||.    '|
+                                                       .. |
+                                                        ''      \nThe rusty endless online proxy: v{}\n",
+            VERSION
+        );
+    }
+
+    tls::init();
+
+    if scripting::enabled() || versions::adapters().next().is_some() {
+        tokio::spawn(scripting::watch());
+    }
+
+    if gaps::enabled() {
+        tokio::spawn(gaps::watch());
+    }
+
+    let tcp_listener =
+        TcpListener::bind(format!("{}:{}", SETTINGS.proxy.host, SETTINGS.proxy.port))
+            .await
+            .unwrap();
+
+    let websocket_listener =
+        TcpListener::bind(format!("127.0.0.1:{}", SETTINGS.proxy.monitor_port))
+            .await
+            .unwrap();
+
+    info!(
+        "listening at {}:{}",
+        SETTINGS.proxy.host, SETTINGS.proxy.port
+    );
+
+    let (tx, _) = broadcast::channel(1024);
+
+    tokio::spawn(monitor::serve(websocket_listener, tx.clone()));
+
+    #[cfg(unix)]
+    tokio::spawn(async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup()).unwrap();
+        while hangup.recv().await.is_some() {
+            match access::reload() {
+                Ok(()) => info!("Reloaded access settings"),
+                Err(e) => error!("Failed to reload access settings: {}", e),
+            }
+        }
+    });
+
+    if let Some(port) = &SETTINGS.proxy.websocket_port {
+        let websocket_client_listener =
+            TcpListener::bind(format!("{}:{}", SETTINGS.proxy.host, port))
+                .await
+                .unwrap();
+
+        info!(
+            "listening for websocket clients at {}:{}",
+            SETTINGS.proxy.host, port
+        );
+
+        tokio::spawn(accept_loop(websocket_client_listener, tx.clone(), true));
+    }
+
+    if cli.tui {
+        let rx = tx.subscribe();
+        tokio::spawn(accept_loop(tcp_listener, tx, false));
+        tui::run(rx).await?;
+    } else {
+        accept_loop(tcp_listener, tx, false).await;
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn accept_loop(listener: TcpListener, tx: broadcast::Sender<WSMessage>, websocket: bool) {
+    loop {
+        let (client_socket, addr) = listener.accept().await.unwrap();
+        tokio::spawn(handle_connection(
+            client_socket,
+            addr,
+            tx.clone(),
+            websocket,
+        ));
+    }
+}
+
+async fn handle_connection(
+    mut client_socket: TcpStream,
+    mut addr: SocketAddr,
+    tx: broadcast::Sender<WSMessage>,
+    websocket: bool,
+) {
+    if SETTINGS.proxy.accept_proxy_protocol {
+        match timeout(
+            PROXY_HEADER_TIMEOUT,
+            proxy_protocol::read_header(&mut client_socket),
+        )
+        .await
+        {
+            Ok(Ok(Some(source))) => addr = source,
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => {
+                warn!("connection dropped ({}): {}", addr, e);
+                return;
+            }
+            Err(_) => {
+                warn!("connection dropped ({}): no PROXY header", addr);
+                return;
+            }
+        }
+    }
+
+    let local_addr = client_socket.local_addr().unwrap_or(addr);
+
+    let client_socket: Box<dyn Stream> = if tls::listener_enabled() {
+        match timeout(TLS_HANDSHAKE_TIMEOUT, tls::accept(client_socket)).await {
+            Ok(Ok(stream)) => Box::new(stream),
+            Ok(Err(e)) => {
+                warn!("tls handshake failed ({}): {}", addr, e);
+                return;
+            }
+            Err(_) => {
+                warn!("tls handshake timed out ({})", addr);
+                return;
+            }
+        }
+    } else {
+        Box::new(client_socket)
+    };
+
+    if websocket {
+        match timeout(
+            WEBSOCKET_HANDSHAKE_TIMEOUT,
+            websocket::accept(client_socket),
+        )
+        .await
+        {
+            Ok(Ok(stream)) => start_session(stream, addr, local_addr, tx).await,
+            Ok(Err(e)) => warn!("websocket handshake failed ({}): {}", addr, e),
+            Err(_) => warn!("websocket handshake timed out ({})", addr),
+        }
+    } else {
+        start_session(client_socket, addr, local_addr, tx).await;
+    }
+}
+
+async fn start_session<S: Stream + 'static>(
+    client_socket: S,
+    addr: SocketAddr,
+    local_addr: SocketAddr,
+    tx: broadcast::Sender<WSMessage>,
+) {
+    let permit = match access::admit(addr.ip()) {
+        Ok(permit) => permit,
+        Err(rejection) => {
+            warn!("connection rejected ({}): {}", addr, rejection);
+            let _ = tx.send(WSMessage::ConnectionRejected {
+                addr: addr.to_string(),
+                reason: rejection.to_string(),
+            });
+            session::reject(client_socket, rejection).await;
+            return;
+        }
+    };
+
+    // Bans answered with a login rejection have to let the client get as far as logging in
+    let ban = bans::check_ip(addr.ip());
+    if let Some(ban) = ban.as_ref().filter(|ban| ban.reply != bans::Reply::Login) {
+        warn!("connection rejected ({}): {}", addr, ban);
+        let _ = tx.send(WSMessage::ConnectionRejected {
+            addr: addr.to_string(),
+            reason: ban.to_string(),
+        });
+        if ban.reply == bans::Reply::Init {
+            let rejection = access::Rejection::Banned {
+                permanent: ban.is_permanent(),
+            };
+            session::reject(client_socket, rejection).await;
+        }
+        return;
+    }
+
+    info!("connection accepted ({})", addr);
+
+    let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+    let span = info_span!(
+        "session",
+        id,
+        addr = %addr,
+        player_id = field::Empty,
+        character = field::Empty,
+        version = field::Empty,
+        hdid = field::Empty,
+    );
+
+    session::run(client_socket, addr, local_addr, id, tx, permit, ban)
+        .instrument(span)
+        .await;
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bot::{Bot, Scenario},
+    cli::LoadArgs,
+};
+
+// Runs many bots at once and reports how long each stage of getting into the game took.
+// Scenario steps are only counted, since most get no reply to time. Sessions start evenly
+// spread over the ramp up, and take their scenarios in turn from the list given
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Stats {
+    // Keyed by stage: connect, login, select_character, enter_game
+    latencies: BTreeMap<&'static str, Vec<Duration>>,
+    errors: BTreeMap<String, u64>,
+    steps: u64,
+    running: u64,
+    completed: u64,
+    failed: u64,
+}
+
+impl Stats {
+    fn record(&mut self, stage: &'static str, started: Instant) {
+        self.latencies
+            .entry(stage)
+            .or_default()
+            .push(started.elapsed());
+    }
+}
+
+type SharedStats = Arc<Mutex<Stats>>;
+
+async fn timed<T, F>(stats: &SharedStats, stage: &'static str, future: F) -> io::Result<T>
+where
+    F: std::future::Future<Output = io::Result<T>>,
+{
+    let started = Instant::now();
+    let result = future.await;
+    match &result {
+        Ok(_) => stats.lock().unwrap().record(stage, started),
+        Err(e) => {
+            *stats
+                .lock()
+                .unwrap()
+                .errors
+                .entry(format!("{}: {}", stage, e))
+                .or_default() += 1;
+        }
+    }
+    result
+}
+
+async fn session(
+    stats: SharedStats,
+    addr: String,
+    version: String,
+    username: String,
+    password: String,
+    scenario: Arc<Scenario>,
+) -> io::Result<()> {
+    let mut bot = timed(&stats, "connect", Bot::connect(&addr, &version)).await?;
+
+    let characters = timed(&stats, "login", bot.login(&username, &password)).await?;
+    let (_, character_id) = match characters.first() {
+        Some(character) => character.clone(),
+        None => {
+            *stats
+                .lock()
+                .unwrap()
+                .errors
+                .entry("login: no characters".to_string())
+                .or_default() += 1;
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no characters"));
+        }
+    };
+
+    timed(
+        &stats,
+        "select_character",
+        bot.select_character(character_id),
+    )
+    .await?;
+    timed(&stats, "enter_game", bot.enter_game()).await?;
+
+    let result = bot.run(&scenario).await;
+    let mut stats = stats.lock().unwrap();
+    stats.steps += bot.steps;
+    if let Err(e) = &result {
+        *stats.errors.entry(format!("scenario: {}", e)).or_default() += 1;
+    }
+    result
+}
+
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let index = (sorted.len() * percent / 100).min(sorted.len() - 1);
+    sorted[index]
+}
+
+fn report(stats: &Stats, elapsed: Duration) {
+    println!(
+        "\n{} sessions completed, {} failed, {} steps in {:.1}s ({:.1} steps/s)",
+        stats.completed,
+        stats.failed,
+        stats.steps,
+        elapsed.as_secs_f64(),
+        stats.steps as f64 / elapsed.as_secs_f64()
+    );
+
+    println!(
+        "\n{:<18} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "stage", "count", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    );
+    for (stage, latencies) in &stats.latencies {
+        let mut sorted = latencies.clone();
+        sorted.sort();
+        println!(
+            "{:<18} {:>8} {:>10.1} {:>10.1} {:>10.1} {:>10.1}",
+            stage,
+            sorted.len(),
+            percentile(&sorted, 50).as_secs_f64() * 1000.0,
+            percentile(&sorted, 90).as_secs_f64() * 1000.0,
+            percentile(&sorted, 99).as_secs_f64() * 1000.0,
+            sorted[sorted.len() - 1].as_secs_f64() * 1000.0
+        );
+    }
+
+    if !stats.errors.is_empty() {
+        println!("\nerrors");
+        for (error, count) in &stats.errors {
+            println!("{:>8} {}", count, error);
+        }
+    }
+}
+
+pub async fn run(args: &LoadArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let scenarios = args
+        .scenarios
+        .iter()
+        .map(|path| {
+            Scenario::load(path)
+                .map(Arc::new)
+                .map_err(|e| format!("{:?}: {}", path, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let stats = SharedStats::default();
+    let started = Instant::now();
+    let ramp_up = Duration::from_secs(args.ramp_up_secs);
+    let mut sessions = Vec::with_capacity(args.sessions);
+
+    let progress = {
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let stats = stats.lock().unwrap();
+                println!(
+                    "{} running, {} completed, {} failed, {} steps",
+                    stats.running, stats.completed, stats.failed, stats.steps
+                );
+            }
+        })
+    };
+
+    for index in 0..args.sessions {
+        let start_at = started + ramp_up.mul_f64(index as f64 / args.sessions as f64);
+        let stats = stats.clone();
+        let addr = args.server.clone();
+        let version = args.version.clone();
+        let username = args.username.replace("{}", &index.to_string());
+        let password = args.password.clone();
+        let scenario = scenarios[index % scenarios.len()].clone();
+
+        sessions.push(tokio::spawn(async move {
+            tokio::time::sleep_until(start_at.into()).await;
+            stats.lock().unwrap().running += 1;
+            let result = session(stats.clone(), addr, version, username, password, scenario).await;
+
+            let mut stats = stats.lock().unwrap();
+            stats.running -= 1;
+            match result {
+                Ok(()) => stats.completed += 1,
+                Err(_) => stats.failed += 1,
+            }
+        }));
+    }
+
+    for session in sessions {
+        let _ = session.await;
+    }
+    progress.abort();
+
+    report(&stats.lock().unwrap(), started.elapsed());
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{
+    fmt::{self, writer::BoxMakeWriter},
+    prelude::*,
+    EnvFilter,
+};
+
+use crate::settings::Log;
+
+// The returned guard flushes the log file when dropped so it must be held for the life of
+// the program. Without a log directory, logs are discarded when stdout is taken by the TUI
+pub fn init(settings: &Log, stdout: bool) -> Option<WorkerGuard> {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(settings.level.as_str()));
+
+    let (writer, guard) = match &settings.directory {
+        Some(directory) => {
+            let appender = match settings.rotation.as_str() {
+                "minutely" => rolling::minutely(directory, &settings.file_prefix),
+                "hourly" => rolling::hourly(directory, &settings.file_prefix),
+                "never" => rolling::never(directory, &settings.file_prefix),
+                _ => rolling::daily(directory, &settings.file_prefix),
+            };
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        None if stdout => (BoxMakeWriter::new(std::io::stdout), None),
+        None => (BoxMakeWriter::new(std::io::sink), None),
+    };
+
+    let layer = if settings.json {
+        fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed()
+    } else {
+        fmt::layer()
+            .with_ansi(settings.directory.is_none())
+            .with_writer(writer)
+            .boxed()
+    };
+
+    let registry = tracing_subscriber::registry().with(layer.with_filter(filter));
+
+    #[cfg(feature = "console")]
+    let registry = registry.with(console_subscriber::spawn());
+
+    registry.init();
+
+    guard
+}
@@ -0,0 +1,219 @@
+use std::{collections::HashMap, io, sync::Arc};
+
+use eo::{
+    data::{EOByte, EOChar, EOInt, EOShort, EOThree, Serializeable, StreamReader},
+    protocol::{
+        client,
+        server::{
+            init::{Init, InitData, InitOk},
+            login, welcome,
+        },
+        CharacterInfo, InitReply, LoginReply, PacketAction, PacketFamily, WelcomeReply,
+    },
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+
+use crate::{bus::Bus, handshake::server_verification_hash, serialize, PacketBuf};
+
+// A stand-in game server for running the proxy without a network. It answers the Init
+// handshake with the configured multiples, then login, character select and entering the
+// game with canned data. Anything else gets the replies registered for it, or is echoed back
+// when echo is set
+
+#[derive(Debug, Clone)]
+pub struct MockCharacter {
+    pub id: EOInt,
+    pub name: String,
+    pub level: EOChar,
+}
+
+// A canned reply sent back in place of the real server
+pub type MockReply = (PacketAction, PacketFamily, PacketBuf);
+
+#[derive(Debug, Clone)]
+pub struct MockConfig {
+    pub encode_multiple: EOByte,
+    pub decode_multiple: EOByte,
+    pub player_id: EOShort,
+    pub characters: Vec<MockCharacter>,
+    pub echo: bool,
+    // Keyed by the action and family bytes of the client packet being answered
+    pub replies: HashMap<(EOByte, EOByte), Vec<MockReply>>,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            encode_multiple: 6,
+            decode_multiple: 10,
+            player_id: 1,
+            characters: vec![MockCharacter {
+                id: 1,
+                name: "mock".to_string(),
+                level: 1,
+            }],
+            echo: false,
+            replies: HashMap::new(),
+        }
+    }
+}
+
+impl MockConfig {
+    // Answers a client packet with another packet, on top of any already registered for it
+    pub fn with_reply(
+        mut self,
+        request: (PacketAction, PacketFamily),
+        reply: (PacketAction, PacketFamily),
+        payload: PacketBuf,
+    ) -> Self {
+        self.replies
+            .entry((request.0.to_byte(), request.1.to_byte()))
+            .or_default()
+            .push((reply.0, reply.1, payload));
+        self
+    }
+}
+
+fn init_ok(config: &MockConfig, challenge: EOThree) -> PacketBuf {
+    let mut ok = InitOk::new();
+    ok.response = server_verification_hash(challenge);
+    ok.player_id = config.player_id;
+    ok.encode_multiple = config.encode_multiple;
+    ok.decode_multiple = config.decode_multiple;
+    // A sequence start of 60, which the server sends as seq1 * 7 + seq2 - 13
+    ok.seq_bytes = [10, 3];
+
+    let mut reply = Init::new();
+    reply.reply_code = InitReply::Ok;
+    reply.data = InitData::Ok(ok);
+    serialize(&reply)
+}
+
+fn login_ok(config: &MockConfig) -> PacketBuf {
+    let mut ok = login::ReplyOk::new();
+    ok.character_list.characters = config
+        .characters
+        .iter()
+        .map(|character| {
+            let mut info = CharacterInfo::new();
+            info.id = character.id;
+            info.name = character.name.clone();
+            info.level = character.level;
+            info
+        })
+        .collect();
+    ok.character_list.num_characters = ok.character_list.characters.len() as EOChar;
+
+    let mut reply = login::Reply::new();
+    reply.reply_code = LoginReply::Ok;
+    reply.data = login::ReplyData::Ok(ok);
+    serialize(&reply)
+}
+
+fn select_character(config: &MockConfig, character: &MockCharacter) -> PacketBuf {
+    let mut select = welcome::ReplySelectCharacter::new();
+    select.session_id = config.player_id;
+    select.character_id = character.id;
+    select.name = character.name.clone();
+    select.level = character.level;
+
+    let mut reply = welcome::Reply::new();
+    reply.reply_code = WelcomeReply::SelectCharacter;
+    reply.data = welcome::ReplyData::SelectCharacter(select);
+    serialize(&reply)
+}
+
+fn enter_game() -> PacketBuf {
+    let mut reply = welcome::Reply::new();
+    reply.reply_code = WelcomeReply::EnterGame;
+    reply.data = welcome::ReplyData::EnterGame(welcome::ReplyEnterGame::new());
+    serialize(&reply)
+}
+
+// Serves each connection until it closes
+pub async fn serve(listener: TcpListener, config: MockConfig) -> io::Result<()> {
+    let config = Arc::new(config);
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(socket, &config).await {
+                debug!("Mock connection {} closed: {}", addr, e);
+            }
+        });
+    }
+}
+
+pub async fn handle<S>(socket: S, config: &MockConfig) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut bus = Bus::new(socket, "Mock".to_string());
+    let mut character = config.characters.first().cloned();
+
+    loop {
+        let packet = match bus.recv().await {
+            Some(Ok(packet)) => packet,
+            Some(Err(e)) => return Err(e),
+            None => continue,
+        };
+        if packet.len() < 2 {
+            continue;
+        }
+
+        let action = PacketAction::from_byte(packet[0]);
+        let family = PacketFamily::from_byte(packet[1]);
+        // Everything the client sends after Init starts with a sequence number
+        let payload = match family {
+            Some(PacketFamily::Init) => &packet[2..],
+            _ => packet.get(3..).unwrap_or_default(),
+        };
+
+        if let Some(replies) = config.replies.get(&(packet[0], packet[1])) {
+            for (action, family, payload) in replies {
+                bus.send(*action, *family, payload.clone()).await?;
+            }
+            continue;
+        }
+
+        match (family, action) {
+            (Some(PacketFamily::Init), Some(PacketAction::Init)) => {
+                let mut init = client::init::Init::new();
+                init.deserialize(&StreamReader::new(payload));
+                let reply = init_ok(config, init.challenge);
+                bus.send(PacketAction::Init, PacketFamily::Init, reply)
+                    .await?;
+                bus.packet_processor
+                    .set_multiples(config.decode_multiple, config.encode_multiple);
+            }
+            (Some(PacketFamily::Login), Some(PacketAction::Request)) => {
+                bus.send(PacketAction::Reply, PacketFamily::Login, login_ok(config))
+                    .await?;
+            }
+            (Some(PacketFamily::Welcome), Some(PacketAction::Request)) => {
+                // The payload starts with the id of the character picked
+                let id = eo::data::decode_number(payload.get(..4).unwrap_or_default());
+                if let Some(picked) = config.characters.iter().find(|c| c.id == id) {
+                    character = Some(picked.clone());
+                }
+
+                if let Some(character) = &character {
+                    let reply = select_character(config, character);
+                    bus.send(PacketAction::Reply, PacketFamily::Welcome, reply)
+                        .await?;
+                }
+            }
+            (Some(PacketFamily::Welcome), Some(PacketAction::Msg)) => {
+                bus.send(PacketAction::Reply, PacketFamily::Welcome, enter_game())
+                    .await?;
+            }
+            (Some(family), Some(action)) if config.echo => {
+                bus.send(action, family, payload.to_vec()).await?;
+            }
+            _ => {}
+        }
+    }
+}
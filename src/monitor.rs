@@ -0,0 +1,281 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{
+        broadcast::{error::RecvError, Sender},
+        oneshot,
+    },
+};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request, Response},
+        http::{header::ORIGIN, StatusCode},
+        protocol::Message,
+    },
+    WebSocketStream,
+};
+
+use crate::{
+    bans::{self, Ban, NewBan},
+    breakpoint,
+    chat::{self, ChatMessage},
+    filter::{Filter, PacketContext},
+    session::{self, Command, Release},
+    tracking::{self, Alt, ConnectionRecord, Query},
+    WSMessage, SETTINGS,
+};
+
+static NEXT_MONITOR_ID: AtomicU64 = AtomicU64::new(1);
+
+// Commands monitors can send back over their WebSocket, as JSON
+#[derive(Debug, Deserialize)]
+enum WSCommand {
+    SetFilter(String),
+    ClearFilter,
+    // Breakpoints apply to every session
+    SetBreakpoint(String),
+    ClearBreakpoint,
+    Release { session_id: u32, action: Release },
+    // Searches the tracking database
+    FindConnections(Query),
+    FindAlts(String),
+    AddBan(NewBan),
+    RemoveBan(u64),
+    ListBans,
+    SearchChat(chat::Query),
+}
+
+// Replies only go to the monitor that sent the command
+#[derive(Debug, Serialize)]
+enum WSReply {
+    FilterSet(String),
+    FilterCleared,
+    BreakpointSet(String),
+    BreakpointCleared,
+    Released(u32),
+    Connections(Vec<ConnectionRecord>),
+    Alts { account: String, alts: Vec<Alt> },
+    BanAdded(Ban),
+    BanRemoved(u64),
+    Bans(Vec<Ban>),
+    Chat(Vec<ChatMessage>),
+    Error(String),
+}
+
+pub async fn serve(listener: TcpListener, tx: Sender<WSMessage>) {
+    loop {
+        let (client_socket, addr) = listener.accept().await.unwrap();
+        info!("New websocket connection from {}", addr);
+
+        let websocket = match accept_hdr_async(client_socket, authorize).await {
+            Ok(ws) => ws,
+            Err(e) => {
+                error!("Failed to accept websocket connection: {}", e);
+                continue;
+            }
+        };
+
+        tokio::spawn(handle(websocket, tx.clone()));
+    }
+}
+
+// Browsers send an Origin with every WebSocket handshake, so checking it stops any page the
+// operator visits from connecting to the monitor. Other clients send none and only need the
+// token, which is passed as ?token= since browsers can't set headers on WebSockets. The large
+// error is what tungstenite's handshake callbacks return
+#[allow(clippy::result_large_err)]
+fn authorize(request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+    let settings = &SETTINGS.monitor;
+
+    if let Some(origin) = request.headers().get(ORIGIN) {
+        let origin = origin.to_str().unwrap_or_default();
+        if !settings
+            .allowed_origins
+            .iter()
+            .any(|allowed| allowed == origin)
+        {
+            warn!("Rejected monitor from origin {}", origin);
+            return Err(reject(StatusCode::FORBIDDEN, "Origin not allowed"));
+        }
+    }
+
+    if let Some(token) = &settings.token {
+        let presented = request
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="));
+        if !presented.is_some_and(|presented| tokens_match(presented, token)) {
+            warn!("Rejected monitor without a valid token");
+            return Err(reject(StatusCode::UNAUTHORIZED, "Invalid token"));
+        }
+    }
+
+    Ok(response)
+}
+
+fn reject(status: StatusCode, reason: &str) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(reason.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+// Compares every byte so the time taken doesn't give away how much of the token was right
+fn tokens_match(presented: &str, token: &str) -> bool {
+    presented.len() == token.len()
+        && presented
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+// Commands that change what the proxy does or read what it has stored. They're only taken once
+// monitor.token is set, as every monitor then had to present it to connect
+fn requires_token(command: &WSCommand) -> bool {
+    matches!(
+        command,
+        WSCommand::Release { .. }
+            | WSCommand::FindConnections(_)
+            | WSCommand::FindAlts(_)
+            | WSCommand::AddBan(_)
+            | WSCommand::RemoveBan(_)
+            | WSCommand::ListBans
+            | WSCommand::SearchChat(_)
+    )
+}
+
+fn allowed(filter: &Option<Filter>, message: &WSMessage) -> bool {
+    match (filter, message) {
+        (
+            Some(filter),
+            WSMessage::Packet {
+                player_id,
+                from,
+                buf,
+                ..
+            },
+        ) => filter.matches(&PacketContext::new(*player_id, from == "Client", buf)),
+        _ => true,
+    }
+}
+
+async fn send<T: serde::Serialize>(
+    websocket: &mut WebSocketStream<TcpStream>,
+    message: &T,
+) -> bool {
+    let message = serde_json::to_string(message).unwrap();
+    websocket.send(Message::text(message)).await.is_ok()
+}
+
+async fn handle(mut websocket: WebSocketStream<TcpStream>, tx: Sender<WSMessage>) {
+    let id = NEXT_MONITOR_ID.fetch_add(1, Ordering::Relaxed);
+    let mut rx = tx.subscribe();
+    let mut filter: Option<Filter> = None;
+
+    loop {
+        tokio::select! {
+            message = rx.recv() => match message {
+                Ok(message) => {
+                    if allowed(&filter, &message) && !send(&mut websocket, &message).await {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Websocket monitor lagged, skipped {} messages", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            incoming = websocket.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = match serde_json::from_str::<WSCommand>(&text) {
+                        Ok(command) if requires_token(&command) && SETTINGS.monitor.token.is_none() => {
+                            WSReply::Error("Set monitor.token to use this command".to_string())
+                        }
+                        Ok(command) => handle_command(id, command, &mut filter).await,
+                        Err(e) => WSReply::Error(format!("Invalid command: {}", e)),
+                    };
+                    if !send(&mut websocket, &reply).await {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    // Nobody is left to release packets held at this monitor's breakpoint
+    if breakpoint::clear_owned_by(id) {
+        info!("Monitor closed, clearing its breakpoint");
+        session::release_all();
+    }
+}
+
+async fn handle_command(id: u64, command: WSCommand, filter: &mut Option<Filter>) -> WSReply {
+    match command {
+        WSCommand::SetFilter(source) => match Filter::parse(&source) {
+            Ok(parsed) => {
+                *filter = Some(parsed);
+                WSReply::FilterSet(source)
+            }
+            Err(e) => WSReply::Error(e.to_string()),
+        },
+        WSCommand::ClearFilter => {
+            *filter = None;
+            WSReply::FilterCleared
+        }
+        WSCommand::SetBreakpoint(source) => match Filter::parse(&source) {
+            Ok(parsed) => {
+                breakpoint::set(id, parsed);
+                WSReply::BreakpointSet(source)
+            }
+            Err(e) => WSReply::Error(e.to_string()),
+        },
+        WSCommand::ClearBreakpoint => {
+            breakpoint::clear();
+            session::release_all();
+            WSReply::BreakpointCleared
+        }
+        WSCommand::Release { session_id, action } => {
+            let (reply, held) = oneshot::channel();
+            let command = Command::Release {
+                action,
+                reply: Some(reply),
+            };
+            if !session::send_command(session_id, command) {
+                return WSReply::Error(format!("Session {} is not connected", session_id));
+            }
+            match held.await {
+                Ok(true) => WSReply::Released(session_id),
+                Ok(false) => WSReply::Error(format!("Session {} has no held packet", session_id)),
+                Err(_) => WSReply::Error(format!("Session {} is not connected", session_id)),
+            }
+        }
+        WSCommand::FindConnections(query) => match tracking::find(query).await {
+            Ok(records) => WSReply::Connections(records),
+            Err(e) => WSReply::Error(e),
+        },
+        WSCommand::FindAlts(account) => match tracking::alts(account.clone()).await {
+            Ok(alts) => WSReply::Alts { account, alts },
+            Err(e) => WSReply::Error(e),
+        },
+        WSCommand::AddBan(ban) => match bans::add(ban) {
+            Ok(ban) => WSReply::BanAdded(ban),
+            Err(e) => WSReply::Error(e),
+        },
+        WSCommand::RemoveBan(id) => match bans::remove(id) {
+            Ok(()) => WSReply::BanRemoved(id),
+            Err(e) => WSReply::Error(e),
+        },
+        WSCommand::ListBans => WSReply::Bans(bans::list()),
+        WSCommand::SearchChat(query) => match chat::search(query).await {
+            Ok(messages) => WSReply::Chat(messages),
+            Err(e) => WSReply::Error(format!("Unable to search chat logs: {}", e)),
+        },
+    }
+}
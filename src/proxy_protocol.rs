@@ -0,0 +1,164 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+use crate::settings::ProxyProtocol;
+
+// https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+const V1_MAX_LENGTH: usize = 107;
+
+pub fn header(version: ProxyProtocol, source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    match version {
+        ProxyProtocol::V1 => header_v1(source, destination),
+        ProxyProtocol::V2 => header_v2(source, destination),
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+pub fn header_v1(source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => format!(
+            "PROXY TCP4 {} {} {} {}\r\n",
+            src,
+            dst,
+            source.port(),
+            destination.port()
+        ),
+        (src, dst) => format!(
+            "PROXY TCP6 {} {} {} {}\r\n",
+            to_ipv6(src),
+            to_ipv6(dst),
+            source.port(),
+            destination.port()
+        ),
+    }
+    .into_bytes()
+}
+
+pub fn header_v2(source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    // Version 2, PROXY command
+    header.push(0x21);
+
+    let mut addresses = Vec::with_capacity(36);
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            // TCP over IPv4
+            header.push(0x11);
+            addresses.extend_from_slice(&src.octets());
+            addresses.extend_from_slice(&dst.octets());
+        }
+        (src, dst) => {
+            // TCP over IPv6
+            header.push(0x21);
+            addresses.extend_from_slice(&to_ipv6(src).octets());
+            addresses.extend_from_slice(&to_ipv6(dst).octets());
+        }
+    }
+    addresses.extend_from_slice(&source.port().to_be_bytes());
+    addresses.extend_from_slice(&destination.port().to_be_bytes());
+
+    header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+    header.append(&mut addresses);
+    header
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid PROXY header: {}", message),
+    )
+}
+
+// Reads a v1 or v2 header from the start of the stream, returning the source address it
+// carries. Health checks from the load balancer carry no address
+pub async fn read_header(socket: &mut TcpStream) -> Result<Option<SocketAddr>> {
+    let mut start = [0; 5];
+    socket.read_exact(&mut start).await?;
+
+    if &start == b"PROXY" {
+        read_v1(socket).await
+    } else if start[..] == V2_SIGNATURE[..5] {
+        read_v2(socket).await
+    } else {
+        Err(invalid("missing signature"))
+    }
+}
+
+async fn read_v1(socket: &mut TcpStream) -> Result<Option<SocketAddr>> {
+    let mut line = b"PROXY".to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LENGTH {
+            return Err(invalid("line too long"));
+        }
+        line.push(socket.read_u8().await?);
+    }
+
+    let line = String::from_utf8(line).map_err(|_| invalid("not ascii"))?;
+    let parts: Vec<&str> = line.trim_end().split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "TCP4" | "TCP6", src, _dst, src_port, _dst_port] => {
+            let ip: IpAddr = src.parse().map_err(|_| invalid("bad source address"))?;
+            let port: u16 = src_port.parse().map_err(|_| invalid("bad source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        _ => Err(invalid("malformed line")),
+    }
+}
+
+async fn read_v2(socket: &mut TcpStream) -> Result<Option<SocketAddr>> {
+    let mut rest = [0; 11];
+    socket.read_exact(&mut rest).await?;
+
+    if rest[..7] != V2_SIGNATURE[5..] {
+        return Err(invalid("bad signature"));
+    }
+
+    let version_command = rest[7];
+    let family = rest[8];
+    let length = u16::from_be_bytes([rest[9], rest[10]]) as usize;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported version"));
+    }
+
+    let mut addresses = vec![0; length];
+    socket.read_exact(&mut addresses).await?;
+
+    // LOCAL connections come from the proxy itself
+    if version_command & 0x0F == 0 {
+        return Ok(None);
+    }
+
+    match family {
+        0x11 if length >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        0x21 if length >= 36 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use eo::protocol::PacketFamily;
+
+use crate::settings::{LimitAction, RateLimit};
+
+struct Bucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new(per_second: f64, burst: u32) -> Self {
+        let capacity = f64::from(burst.max(1));
+        Self {
+            capacity,
+            tokens: capacity,
+            per_second,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    // Returns how long the caller would have to wait for a token, taking it if one is
+    // available now
+    fn take(&mut self, now: Instant) -> Option<Duration> {
+        let wait = self.wait(now);
+        if wait.is_none() {
+            self.tokens -= 1.0;
+        }
+        wait
+    }
+
+    fn wait(&mut self, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            None
+        } else if self.per_second > 0.0 {
+            Some(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.per_second,
+            ))
+        } else {
+            Some(Duration::MAX)
+        }
+    }
+
+    // Takes a token that hasn't been earned yet, for packets that are delayed rather than
+    // dropped
+    fn borrow(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allow,
+    Drop,
+    Delay(Duration),
+    Disconnect,
+    Flag,
+}
+
+pub struct RateLimiter {
+    enabled: bool,
+    session: Bucket,
+    action: LimitAction,
+    families: HashMap<String, (Bucket, LimitAction)>,
+    max_delay: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimit) -> Self {
+        Self {
+            enabled: settings.enabled,
+            session: Bucket::new(settings.packets_per_second, settings.burst),
+            action: settings.action,
+            families: settings
+                .families
+                .iter()
+                .map(|(family, limit)| {
+                    (
+                        family.clone(),
+                        (
+                            Bucket::new(limit.packets_per_second, limit.burst),
+                            limit.action.unwrap_or(settings.action),
+                        ),
+                    )
+                })
+                .collect(),
+            max_delay: Duration::from_millis(settings.max_delay_ms),
+        }
+    }
+
+    // Packets of unknown families only count against the session's limit
+    pub fn check(&mut self, family: Option<PacketFamily>) -> Verdict {
+        if !self.enabled {
+            return Verdict::Allow;
+        }
+
+        let now = Instant::now();
+
+        let limit = family.and_then(|family| self.families.get_mut(&format!("{:?}", family)));
+        if let Some((bucket, action)) = limit {
+            if let Some(wait) = bucket.take(now) {
+                // A delayed packet is still sent, so it waits for and counts against the
+                // session's limit as well
+                let wait = match self.session.wait(now) {
+                    Some(session_wait) => wait.max(session_wait),
+                    None => wait,
+                };
+                let verdict = verdict(*action, wait, self.max_delay);
+                if let Verdict::Delay(_) = verdict {
+                    bucket.borrow();
+                    self.session.borrow();
+                }
+                return verdict;
+            }
+        }
+
+        match self.session.take(now) {
+            Some(wait) => {
+                let verdict = verdict(self.action, wait, self.max_delay);
+                if let Verdict::Delay(_) = verdict {
+                    self.session.borrow();
+                }
+                verdict
+            }
+            None => Verdict::Allow,
+        }
+    }
+}
+
+fn verdict(action: LimitAction, wait: Duration, max_delay: Duration) -> Verdict {
+    match action {
+        LimitAction::Drop => Verdict::Drop,
+        // Holding a packet longer than this is no better than losing it
+        LimitAction::Delay if wait > max_delay => Verdict::Drop,
+        LimitAction::Delay => Verdict::Delay(wait),
+        LimitAction::Disconnect => Verdict::Disconnect,
+        LimitAction::Flag => Verdict::Flag,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::FamilyLimit;
+
+    use super::*;
+
+    #[test]
+    fn delayed_family_packets_count_against_the_session() {
+        let mut settings = RateLimit {
+            enabled: true,
+            packets_per_second: 1.0,
+            burst: 2,
+            ..Default::default()
+        };
+        settings.families.insert(
+            "Walk".to_string(),
+            FamilyLimit {
+                packets_per_second: 1.0,
+                burst: 1,
+                action: Some(LimitAction::Delay),
+            },
+        );
+        let mut limiter = RateLimiter::new(&settings);
+
+        assert_eq!(limiter.check(Some(PacketFamily::Walk)), Verdict::Allow);
+        assert!(matches!(
+            limiter.check(Some(PacketFamily::Walk)),
+            Verdict::Delay(_)
+        ));
+        // The delayed walk took the session's last token
+        assert_eq!(limiter.check(Some(PacketFamily::Talk)), Verdict::Drop);
+    }
+}
@@ -0,0 +1,34 @@
+use eo::protocol::{
+    server::{
+        init::{Init, InitBanned, InitBannedData, InitBannedTemp, InitData},
+        login,
+    },
+    InitBanType, InitReply, LoginReply,
+};
+
+use crate::{serialize, PacketBuf};
+
+// Server replies the proxy sends on its own when it turns a client away
+
+pub fn init_banned(permanent: bool) -> PacketBuf {
+    let mut banned = InitBanned::new();
+    if permanent {
+        banned.ban_type = InitBanType::Perm;
+        banned.data = InitBannedData::None;
+    } else {
+        banned.ban_type = InitBanType::Temp;
+        banned.data = InitBannedData::Temp(InitBannedTemp::new());
+    }
+
+    let mut reply = Init::new();
+    reply.reply_code = InitReply::Banned;
+    reply.data = InitData::Banned(banned);
+    serialize(&reply)
+}
+
+pub fn login_banned() -> PacketBuf {
+    let mut reply = login::Reply::new();
+    reply.reply_code = LoginReply::Banned;
+    reply.data = login::ReplyData::Banned(login::ReplyBanned::new());
+    serialize(&reply)
+}
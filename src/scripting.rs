@@ -0,0 +1,415 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use lazy_static::lazy_static;
+use rhai::{
+    serde::{from_dynamic, to_dynamic},
+    Dynamic, Engine, EvalAltResult, Map, Scope, AST,
+};
+use serde_json::Value;
+
+use crate::{decode, versions, PacketBuf, SETTINGS};
+
+// Scripts in the scripts directory can define any of these hooks:
+//
+//   fn on_client_packet(session, packet) { ... }
+//   fn on_server_packet(session, packet) { ... }
+//
+// session has id, player_id, addr and character. packet has family, action, buf (the whole
+// packet as an array of bytes) and decoded (the protocol struct as a map, or () when the
+// packet can't be decoded). Hooks return () or true to pass the packet on, false to drop it,
+// or a packet map to replace it. Replacements are re-encoded from decoded if that was
+// changed, otherwise buf is sent as is.
+//
+// send_client(family, action, data) and send_server(family, action, data) inject new packets,
+// where data is either a map of fields or an array of payload bytes. The proxy numbers every
+// packet it sends the server, injected or not, so scripts never deal with sequence numbers.
+//
+// Adapters for version mappings (see versions) are scripts with the same hooks, run only for
+// sessions on the mapped client version.
+
+const CLIENT_HOOK: &str = "on_client_packet";
+const SERVER_HOOK: &str = "on_server_packet";
+
+// Hooks run for every packet, so a runaway script mustn't be able to stall its session
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_STRING_SIZE: usize = 64 * 1024;
+
+lazy_static! {
+    static ref ENGINE: Engine = engine();
+    static ref SCRIPTS: RwLock<Scripts> = RwLock::new(Scripts::default());
+}
+
+thread_local! {
+    static INJECTED: RefCell<Vec<Injection>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Script {
+    modified: SystemTime,
+    ast: Arc<AST>,
+}
+
+#[derive(Default)]
+struct Scripts {
+    // Sorted by path so scripts run in a predictable order
+    loaded: BTreeMap<PathBuf, Script>,
+    adapters: BTreeMap<PathBuf, Script>,
+}
+
+pub struct SessionInfo<'a> {
+    pub id: u32,
+    pub player_id: u32,
+    pub addr: String,
+    pub character: Option<&'a str>,
+}
+
+#[derive(Debug)]
+pub struct Injection {
+    pub to_client: bool,
+    pub buf: PacketBuf,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Forward(PacketBuf),
+    Drop,
+}
+
+fn inject(
+    to_client: bool,
+    family: &str,
+    action: &str,
+    data: Dynamic,
+) -> Result<(), Box<EvalAltResult>> {
+    let family = decode::family_from_name(family)
+        .ok_or_else(|| format!("Unknown packet family {}", family))?;
+    let action = decode::action_from_name(action)
+        .ok_or_else(|| format!("Unknown packet action {}", action))?;
+
+    // The sequence byte is filled in when the injection is sent
+    let from_client = !to_client;
+    let sequence = decode::has_sequence(from_client, family).then_some(0);
+
+    let buf = if data.is_map() {
+        let value: Value = from_dynamic(&data)?;
+        decode::encode(from_client, family, action, sequence, value)
+            .ok_or("Unable to encode packet")?
+    } else {
+        let mut payload: Vec<u8> = from_dynamic(&data)?;
+        let mut buf = vec![action.to_byte(), family.to_byte()];
+        buf.extend(sequence);
+        buf.append(&mut payload);
+        buf
+    };
+
+    INJECTED.with(|injected| injected.borrow_mut().push(Injection { to_client, buf }));
+    Ok(())
+}
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine.set_max_string_size(MAX_STRING_SIZE);
+    engine.register_fn(
+        "send_client",
+        |family: &str, action: &str, data: Dynamic| inject(true, family, action, data),
+    );
+    engine.register_fn(
+        "send_server",
+        |family: &str, action: &str, data: Dynamic| inject(false, family, action, data),
+    );
+    engine.on_print(|text| info!("[script] {}", text));
+    engine.on_debug(|text, source, position| {
+        debug!(
+            "[script] {} {:?} {}",
+            source.unwrap_or_default(),
+            position,
+            text
+        )
+    });
+    engine
+}
+
+fn compile(path: &Path) -> Option<Arc<AST>> {
+    let compiled = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|source| ENGINE.compile(source).map_err(|e| e.to_string()));
+
+    match compiled {
+        Ok(ast) => {
+            info!("Loaded script {:?}", path);
+            Some(Arc::new(ast))
+        }
+        // Keep running the last good version until the script is fixed
+        Err(e) => {
+            error!("Failed to load script {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+impl Scripts {
+    fn reload(&mut self, directory: &Path) {
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Unable to read scripts directory {:?}: {}", directory, e);
+                return;
+            }
+        };
+
+        let mut found = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "rhai") {
+                continue;
+            }
+
+            let modified = match entry.metadata().and_then(|m| m.modified()) {
+                Ok(modified) => modified,
+                Err(_) => continue,
+            };
+            found.push(path.clone());
+
+            if matches!(self.loaded.get(&path), Some(script) if script.modified == modified) {
+                continue;
+            }
+
+            if let Some(ast) = compile(&path) {
+                self.loaded.insert(path, Script { modified, ast });
+            }
+        }
+
+        self.loaded.retain(|path, _| {
+            let keep = found.contains(path);
+            if !keep {
+                info!("Unloaded script {:?}", path);
+            }
+            keep
+        });
+    }
+
+    fn reload_adapters(&mut self) {
+        for path in versions::adapters().map(PathBuf::from) {
+            let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+                Ok(modified) => modified,
+                Err(e) => {
+                    if !self.adapters.contains_key(&path) {
+                        warn!("Unable to read adapter {:?}: {}", path, e);
+                    }
+                    continue;
+                }
+            };
+
+            if matches!(self.adapters.get(&path), Some(script) if script.modified == modified) {
+                continue;
+            }
+
+            if let Some(ast) = compile(&path) {
+                self.adapters.insert(path, Script { modified, ast });
+            }
+        }
+    }
+
+    // The scripts with the hook, so they can be run without holding the lock
+    fn with_hook(&self, hook: &str, adapter: Option<&Path>) -> Vec<(PathBuf, Arc<AST>)> {
+        let scripts: Vec<(&PathBuf, &Script)> = match adapter {
+            Some(adapter) => self.adapters.get_key_value(adapter).into_iter().collect(),
+            None => self.loaded.iter().collect(),
+        };
+
+        scripts
+            .into_iter()
+            .filter(|(_, script)| script.ast.iter_functions().any(|f| f.name == hook))
+            .map(|(path, script)| (path.clone(), script.ast.clone()))
+            .collect()
+    }
+}
+
+fn call(
+    hook: &str,
+    scripts: Vec<(PathBuf, Arc<AST>)>,
+    session: &SessionInfo,
+    from_client: bool,
+    buf: PacketBuf,
+) -> Outcome {
+    let mut buf = buf;
+
+    for (path, ast) in scripts {
+        let decoded = decode::decode(from_client, &buf);
+        let packet = match packet_map(&buf, &decoded) {
+            Ok(packet) => packet,
+            Err(e) => {
+                error!("Unable to pass packet to script: {}", e);
+                return Outcome::Forward(buf);
+            }
+        };
+
+        let result = ENGINE.call_fn::<Dynamic>(
+            &mut Scope::new(),
+            &ast,
+            hook,
+            (session_map(session), packet),
+        );
+
+        match result {
+            Ok(result) if result.is_unit() => {}
+            Ok(result) if result.is_bool() => {
+                if !result.as_bool().unwrap_or(true) {
+                    return Outcome::Drop;
+                }
+            }
+            Ok(result) if result.is_map() => {
+                match replacement(from_client, &buf, &decoded, result) {
+                    Ok(replaced) => buf = replaced,
+                    Err(e) => error!("{:?} {} returned a bad packet: {}", path, hook, e),
+                }
+            }
+            Ok(result) => error!(
+                "{:?} {} returned {}, expected a packet, true or false",
+                path,
+                hook,
+                result.type_name()
+            ),
+            Err(e) => error!("{:?} {} failed: {}", path, hook, e),
+        }
+    }
+
+    Outcome::Forward(buf)
+}
+
+fn session_map(session: &SessionInfo) -> Map {
+    let mut map = Map::new();
+    map.insert("id".into(), Dynamic::from_int(session.id.into()));
+    map.insert(
+        "player_id".into(),
+        Dynamic::from_int(session.player_id.into()),
+    );
+    map.insert("addr".into(), session.addr.clone().into());
+    map.insert(
+        "character".into(),
+        session
+            .character
+            .map_or(Dynamic::UNIT, |character| character.to_string().into()),
+    );
+    map
+}
+
+fn packet_map(buf: &[u8], decoded: &Option<Value>) -> Result<Map, Box<EvalAltResult>> {
+    let name = decode::packet_name(buf);
+    let (family, action) = name.split_once('_').unwrap_or((&name, ""));
+
+    let mut map = Map::new();
+    map.insert("family".into(), family.to_string().into());
+    map.insert("action".into(), action.to_string().into());
+    map.insert("buf".into(), to_dynamic(buf)?);
+    map.insert(
+        "decoded".into(),
+        match decoded {
+            Some(value) => to_dynamic(value)?,
+            None => Dynamic::UNIT,
+        },
+    );
+    Ok(map)
+}
+
+fn replacement(
+    from_client: bool,
+    original: &[u8],
+    original_decoded: &Option<Value>,
+    result: Dynamic,
+) -> Result<PacketBuf, Box<EvalAltResult>> {
+    let map = result.cast::<Map>();
+
+    if let (Some(decoded), Some(original_decoded)) = (map.get("decoded"), original_decoded) {
+        let decoded: Value = from_dynamic(decoded)?;
+        if decoded != *original_decoded {
+            let family = decode::family_from_name(&map_string(&map, "family")?)
+                .ok_or("Unknown packet family")?;
+            let action = decode::action_from_name(&map_string(&map, "action")?)
+                .ok_or("Unknown packet action")?;
+            let sequence = original.get(2).copied();
+            return Ok(
+                decode::encode(from_client, family, action, sequence, decoded)
+                    .ok_or("Unable to encode packet")?,
+            );
+        }
+    }
+
+    let buf: PacketBuf = from_dynamic(map.get("buf").ok_or("Packet has no buf")?)?;
+    if buf.len() < 2 {
+        return Err("Packet is too short".into());
+    }
+    Ok(buf)
+}
+
+fn map_string(map: &Map, key: &str) -> Result<String, Box<EvalAltResult>> {
+    map.get(key)
+        .and_then(|value| value.clone().into_string().ok())
+        .ok_or_else(|| format!("Packet has no {}", key).into())
+}
+
+pub fn enabled() -> bool {
+    SETTINGS.scripting.enabled
+}
+
+fn run_hook(
+    hook: &str,
+    adapter: Option<&Path>,
+    session: &SessionInfo,
+    from_client: bool,
+    buf: PacketBuf,
+) -> (Outcome, Vec<Injection>) {
+    INJECTED.with(|injected| injected.borrow_mut().clear());
+    let scripts = SCRIPTS.read().unwrap().with_hook(hook, adapter);
+    let outcome = call(hook, scripts, session, from_client, buf);
+    let injected = INJECTED.with(|injected| injected.borrow_mut().drain(..).collect());
+    (outcome, injected)
+}
+
+pub fn client_packet(session: &SessionInfo, buf: PacketBuf) -> (Outcome, Vec<Injection>) {
+    run_hook(CLIENT_HOOK, None, session, true, buf)
+}
+
+pub fn server_packet(session: &SessionInfo, buf: PacketBuf) -> (Outcome, Vec<Injection>) {
+    run_hook(SERVER_HOOK, None, session, false, buf)
+}
+
+// Runs just the adapter's hook for the packet
+pub fn adapt(
+    adapter: &Path,
+    session: &SessionInfo,
+    from_client: bool,
+    buf: PacketBuf,
+) -> (Outcome, Vec<Injection>) {
+    let hook = if from_client {
+        CLIENT_HOOK
+    } else {
+        SERVER_HOOK
+    };
+    run_hook(hook, Some(adapter), session, from_client, buf)
+}
+
+// Loads scripts and adapters and keeps checking them for changes
+pub async fn watch() {
+    let directory = PathBuf::from(&SETTINGS.scripting.directory);
+    let mut interval =
+        tokio::time::interval(Duration::from_millis(SETTINGS.scripting.poll_interval_ms));
+
+    loop {
+        interval.tick().await;
+        let mut scripts = SCRIPTS.write().unwrap();
+        if enabled() {
+            scripts.reload(&directory);
+        }
+        scripts.reload_adapters();
+    }
+}
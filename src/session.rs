@@ -0,0 +1,1016 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
+use eo::{
+    data::{EOInt, EOShort, Serializeable, StreamReader},
+    net::ClientSequencer,
+    protocol::{
+        client,
+        server::{
+            connection,
+            init::{Init, InitData},
+            login, welcome,
+        },
+        PacketAction, PacketFamily,
+    },
+};
+use lazy_static::lazy_static;
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpStream,
+    sync::{
+        broadcast::Sender,
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot, Semaphore,
+    },
+    time::{timeout, Instant},
+};
+use tracing::Span;
+
+use crate::{
+    access::{Permit, Rejection},
+    bans::{self, Ban, Reply as BanReply},
+    breakpoint,
+    bus::{Bus, Stream},
+    capture::CaptureWriter,
+    chat::{self, Channel, ChatMessage, Talk},
+    fuzz::Fuzzer,
+    gaps,
+    handshake::{Handshake, Outcome as HandshakeOutcome},
+    latency::LatencyTracker,
+    proxy_protocol,
+    rate_limit::{RateLimiter, Verdict},
+    replies,
+    scripting::{self, Injection, Outcome, SessionInfo},
+    tls, tracking, versions, PacketBuf, WSMessage, SETTINGS,
+};
+
+const REJECT_TIMEOUT: Duration = Duration::from_secs(5);
+// For connecting to the server and the TLS handshake with it
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(10);
+// Rejected clients wait on the proxy, so a flood of them mustn't be able to pile up
+const MAX_REJECTIONS: usize = 256;
+
+lazy_static! {
+    static ref REJECTIONS: Semaphore = Semaphore::new(MAX_REJECTIONS);
+    static ref SESSIONS: Mutex<HashMap<u32, UnboundedSender<Command>>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Deserialize)]
+pub enum Release {
+    Forward,
+    Edit(Vec<u8>),
+    Drop,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    // Answers whether a packet was held
+    Release {
+        action: Release,
+        reply: Option<oneshot::Sender<bool>>,
+    },
+}
+
+// Returns false if the session has already closed
+pub fn send_command(session_id: u32, command: Command) -> bool {
+    match SESSIONS.lock().unwrap().get(&session_id) {
+        Some(tx) => tx.send(command).is_ok(),
+        None => false,
+    }
+}
+
+// Lets go of every held packet, for when there's no breakpoint left to hold them
+pub fn release_all() {
+    for tx in SESSIONS.lock().unwrap().values() {
+        let _ = tx.send(Command::Release {
+            action: Release::Forward,
+            reply: None,
+        });
+    }
+}
+
+struct Registration(u32);
+
+impl Registration {
+    fn new(session_id: u32) -> (Self, UnboundedReceiver<Command>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        SESSIONS.lock().unwrap().insert(session_id, tx);
+        (Self(session_id), rx)
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        SESSIONS.lock().unwrap().remove(&self.0);
+    }
+}
+
+#[derive(PartialEq)]
+enum Flow {
+    Continue,
+    Close,
+}
+
+// A client packet waiting out the rate limit
+struct DelayedPacket {
+    until: Instant,
+    packet: PacketBuf,
+    received_at: DateTime<Local>,
+}
+
+struct HeldPacket {
+    from_client: bool,
+    buf: PacketBuf,
+    received_at: DateTime<Local>,
+}
+
+struct Session {
+    id: u32,
+    addr: SocketAddr,
+    tx: Sender<WSMessage>,
+    client_bus: Bus,
+    server_bus: Bus,
+    client_queue: VecDeque<(PacketBuf, DateTime<Local>)>,
+    server_queue: VecDeque<(PacketBuf, DateTime<Local>)>,
+    held: Option<HeldPacket>,
+    delayed: VecDeque<DelayedPacket>,
+    latency: LatencyTracker,
+    rate_limiter: RateLimiter,
+    player_id: EOShort,
+    account: Option<String>,
+    character: Option<String>,
+    map_id: Option<EOShort>,
+    // Numbers packets as the proxy sends them, since it drops and injects its own
+    sequencer: ClientSequencer,
+    capture: Option<CaptureWriter>,
+    fuzzer: Option<Fuzzer>,
+    handshake: Handshake,
+    // Set when the client's version is mapped to one with an adapter
+    adapter: Option<PathBuf>,
+    // Ids and names from the account's character list, for character bans
+    characters: Vec<(EOInt, String)>,
+    // A ban found before login that is answered with a login rejection
+    pending_ban: Option<Ban>,
+}
+
+pub async fn run<S: Stream + 'static>(
+    client_socket: S,
+    addr: SocketAddr,
+    local_addr: SocketAddr,
+    id: u32,
+    tx: Sender<WSMessage>,
+    _permit: Permit,
+    ban: Option<Ban>,
+) {
+    let server_addr = format!("{}:{}", SETTINGS.server.host, SETTINGS.server.port);
+    let mut server_socket = match timeout(UPSTREAM_TIMEOUT, TcpStream::connect(server_addr)).await {
+        Ok(Ok(socket)) => socket,
+        Ok(Err(e)) => {
+            error!("Failed to connect to server for {}: {}", addr, e);
+            return;
+        }
+        Err(_) => {
+            error!("Timed out connecting to server for {}", addr);
+            return;
+        }
+    };
+
+    if let Some(version) = SETTINGS.server.proxy_protocol {
+        let header = proxy_protocol::header(version, addr, local_addr);
+        if let Err(e) = server_socket.write_all(&header).await {
+            error!("Failed to send PROXY header: {}", e);
+            return;
+        }
+    }
+
+    let server_socket = match timeout(UPSTREAM_TIMEOUT, tls::connect(server_socket)).await {
+        Ok(Ok(socket)) => socket,
+        Ok(Err(e)) => {
+            error!("Failed to establish TLS with server: {}", e);
+            return;
+        }
+        Err(_) => {
+            error!("Timed out establishing TLS with server");
+            return;
+        }
+    };
+
+    let (_registration, mut commands) = Registration::new(id);
+
+    tracking::connected(id, addr.ip());
+    let mut session = Session {
+        id,
+        addr,
+        tx,
+        client_bus: Bus::new(client_socket, "Client".to_string()),
+        server_bus: Bus::new(server_socket, "Server".to_string()),
+        client_queue: VecDeque::new(),
+        server_queue: VecDeque::new(),
+        held: None,
+        delayed: VecDeque::new(),
+        latency: LatencyTracker::new(),
+        rate_limiter: RateLimiter::new(&SETTINGS.rate_limit),
+        player_id: 0,
+        account: None,
+        character: None,
+        map_id: None,
+        sequencer: ClientSequencer::default(),
+        capture: CaptureWriter::for_session(&SETTINGS.capture, id, addr),
+        fuzzer: Fuzzer::for_session(&SETTINGS.fuzz, id, addr),
+        handshake: Handshake::default(),
+        adapter: None,
+        characters: Vec::new(),
+        pending_ban: ban,
+    };
+
+    let _ = session.tx.send(WSMessage::AddPlayer { session_id: id });
+
+    loop {
+        let hang_deadline = session.fuzzer.as_ref().and_then(Fuzzer::deadline);
+        let delayed_until = session.delayed.front().map(|delayed| delayed.until);
+
+        tokio::select! {
+            result = session.client_bus.recv() => match result {
+                Some(Ok(packet)) => {
+                    let received_at = session.client_bus.timestamp();
+                    session.client_queue.push_back((packet, received_at));
+                },
+                Some(Err(e)) => {
+                    match e.kind() {
+                        std::io::ErrorKind::BrokenPipe => info!("Client Closed by peer"),
+                        _ => error!("Unknown error: {}", e),
+                    }
+                    break;
+                },
+                None => {
+                }
+            },
+            result = session.server_bus.recv() => match result {
+                Some(Ok(packet)) => {
+                    let received_at = session.server_bus.timestamp();
+                    session.server_queue.push_back((packet, received_at));
+                },
+                Some(Err(e)) => {
+                    match e.kind() {
+                        std::io::ErrorKind::BrokenPipe => info!("Server Closed by peer"),
+                        _ => error!("Unknown error: {}", e),
+                    }
+                    if let Some(fuzzer) = &mut session.fuzzer {
+                        fuzzer.server_closed();
+                    }
+                    break;
+                },
+                None => {
+                }
+            },
+            Some(command) = commands.recv() => {
+                if session.handle_command(command).await == Flow::Close {
+                    break;
+                }
+            }
+            _ = tokio::time::sleep_until(delayed_until.unwrap_or_else(Instant::now)),
+                if delayed_until.is_some() =>
+            {
+                session.send_delayed().await;
+            }
+            _ = tokio::time::sleep_until(hang_deadline.unwrap_or_else(Instant::now)),
+                if hang_deadline.is_some() =>
+            {
+                if let Some(fuzzer) = &mut session.fuzzer {
+                    fuzzer.hang();
+                }
+            }
+        }
+
+        if session.drain().await == Flow::Close {
+            break;
+        }
+    }
+
+    let _ = session.tx.send(WSMessage::RemovePlayer {
+        session_id: session.id,
+        player_id: session.player_id.into(),
+    });
+    tracking::disconnected(session.id);
+
+    info!(
+        average_response_ms = session.latency.average_response().num_milliseconds(),
+        client_rtt_ms = session
+            .latency
+            .client_rtt()
+            .map(|d| d.num_milliseconds())
+            .unwrap_or_default(),
+        average_relay_us = session
+            .latency
+            .average_relay()
+            .num_microseconds()
+            .unwrap_or_default(),
+        "Session closed"
+    );
+}
+
+impl Session {
+    async fn handle_command(&mut self, command: Command) -> Flow {
+        match command {
+            Command::Release { action, reply } => {
+                let held = self.held.take();
+                if let Some(reply) = reply {
+                    let _ = reply.send(held.is_some());
+                }
+                let held = match held {
+                    Some(held) => held,
+                    None => return Flow::Continue,
+                };
+
+                let buf = match action {
+                    Release::Forward => held.buf,
+                    Release::Edit(buf) if buf.len() >= 2 => buf,
+                    Release::Edit(_) => {
+                        warn!("Dropping held packet, edited packet is too short");
+                        return Flow::Continue;
+                    }
+                    Release::Drop => {
+                        debug!("Dropping held packet");
+                        return Flow::Continue;
+                    }
+                };
+
+                if held.from_client {
+                    self.forward_client(buf, held.received_at).await
+                } else {
+                    self.forward_server(buf, held.received_at).await;
+                    Flow::Continue
+                }
+            }
+        }
+    }
+
+    // Handles queued packets in the order they arrived on each side, until both queues are
+    // empty or one is held at a breakpoint. Everything waits behind a held packet
+    async fn drain(&mut self) -> Flow {
+        while self.held.is_none() {
+            let mut handled = false;
+
+            if let Some((packet, received_at)) = self.client_queue.pop_front() {
+                handled = true;
+                if self.client_packet(packet, received_at).await == Flow::Close {
+                    return Flow::Close;
+                }
+            }
+
+            if self.held.is_some() {
+                break;
+            }
+
+            if let Some((packet, received_at)) = self.server_queue.pop_front() {
+                handled = true;
+                if self.server_packet(packet, received_at).await == Flow::Close {
+                    return Flow::Close;
+                }
+            }
+
+            if !handled {
+                break;
+            }
+        }
+        Flow::Continue
+    }
+
+    fn hold(&mut self, from_client: bool, buf: PacketBuf, received_at: DateTime<Local>) {
+        info!("Holding packet at breakpoint: {:?}", buf);
+        let _ = self.tx.send(WSMessage::Breakpoint {
+            session_id: self.id,
+            player_id: self.player_id.into(),
+            from: if from_client { "Client" } else { "Server" }.to_string(),
+            buf: buf.clone(),
+        });
+        self.held = Some(HeldPacket {
+            from_client,
+            buf,
+            received_at,
+        });
+    }
+
+    fn script_session(&self) -> SessionInfo<'_> {
+        SessionInfo {
+            id: self.id,
+            player_id: self.player_id.into(),
+            addr: self.addr.to_string(),
+            character: self.character.as_deref(),
+        }
+    }
+
+    // The adapter runs nearest the client, so scripts only see packets as the server speaks them
+    fn run_hooks(&self, from_client: bool, packet: PacketBuf) -> (Outcome, Vec<Injection>) {
+        let session = self.script_session();
+        let adapter = self.adapter.as_deref();
+        let order = if from_client {
+            [true, false]
+        } else {
+            [false, true]
+        };
+
+        let mut packet = packet;
+        let mut injected = Vec::new();
+        for run_adapter in order {
+            let (outcome, mut more) = match (run_adapter, adapter) {
+                (true, Some(adapter)) => scripting::adapt(adapter, &session, from_client, packet),
+                (false, _) if scripting::enabled() => {
+                    if from_client {
+                        scripting::client_packet(&session, packet)
+                    } else {
+                        scripting::server_packet(&session, packet)
+                    }
+                }
+                _ => continue,
+            };
+
+            injected.append(&mut more);
+            match outcome {
+                Outcome::Forward(forwarded) => packet = forwarded,
+                Outcome::Drop => return (Outcome::Drop, injected),
+            }
+        }
+
+        (Outcome::Forward(packet), injected)
+    }
+
+    async fn client_packet(&mut self, packet: PacketBuf, received_at: DateTime<Local>) -> Flow {
+        let (outcome, injected) = self.run_hooks(true, packet);
+
+        if let Outcome::Forward(packet) = outcome {
+            if breakpoint::hit(self.player_id.into(), true, &packet) {
+                self.hold(true, packet, received_at);
+            } else if self.forward_client(packet, received_at).await == Flow::Close {
+                return Flow::Close;
+            }
+        }
+
+        self.send_injected(injected).await
+    }
+
+    async fn server_packet(&mut self, packet: PacketBuf, received_at: DateTime<Local>) -> Flow {
+        let (outcome, injected) = self.run_hooks(false, packet);
+
+        if let Outcome::Forward(packet) = outcome {
+            if breakpoint::hit(self.player_id.into(), false, &packet) {
+                self.hold(false, packet, received_at);
+            } else {
+                self.forward_server(packet, received_at).await;
+            }
+        }
+
+        self.send_injected(injected).await
+    }
+
+    async fn send_injected(&mut self, injected: Vec<Injection>) -> Flow {
+        for Injection { to_client, buf } in injected {
+            if to_client {
+                self.forward_server(buf, Local::now()).await;
+                continue;
+            }
+
+            if self.forward_client(buf, Local::now()).await == Flow::Close {
+                return Flow::Close;
+            }
+        }
+
+        Flow::Continue
+    }
+
+    async fn forward_client(
+        &mut self,
+        mut packet: PacketBuf,
+        received_at: DateTime<Local>,
+    ) -> Flow {
+        let known = known(&packet);
+
+        match known {
+            Some((action, family)) => {
+                debug!(
+                    family = ?family,
+                    action = ?action,
+                    len = packet.len(),
+                    "From client: {:?}",
+                    packet
+                );
+                self.reply_received(true, family, action, received_at);
+                if self.inspect_client(action, family, &mut packet).await == Flow::Close {
+                    return Flow::Close;
+                }
+            }
+            None => debug!("From client: unknown packet {:?}", packet),
+        }
+
+        let verdict = self.rate_limiter.check(known.map(|(_, family)| family));
+        if verdict != Verdict::Allow {
+            let (family, action) = match known {
+                Some((action, family)) => (format!("{:?}", family), format!("{:?}", action)),
+                None => ("Unknown".to_string(), "Unknown".to_string()),
+            };
+            warn!(
+                family = family.as_str(),
+                action = action.as_str(),
+                "Rate limit exceeded: {:?}",
+                verdict
+            );
+            let _ = self.tx.send(WSMessage::RateLimited {
+                session_id: self.id,
+                player_id: self.player_id as u32,
+                family,
+                action,
+                verdict: format!("{:?}", verdict),
+            });
+        }
+
+        match verdict {
+            Verdict::Drop => {}
+            Verdict::Disconnect => return Flow::Close,
+            Verdict::Delay(wait) => self.delay(Instant::now() + wait, packet, received_at),
+            // Nothing overtakes a delayed packet
+            _ if !self.delayed.is_empty() => self.delay(Instant::now(), packet, received_at),
+            _ => self.send_client(packet, received_at).await,
+        }
+
+        Flow::Continue
+    }
+
+    fn delay(&mut self, until: Instant, packet: PacketBuf, received_at: DateTime<Local>) {
+        let until = match self.delayed.back() {
+            Some(last) => until.max(last.until),
+            None => until,
+        };
+        self.delayed.push_back(DelayedPacket {
+            until,
+            packet,
+            received_at,
+        });
+    }
+
+    async fn send_delayed(&mut self) {
+        let now = Instant::now();
+        while self
+            .delayed
+            .front()
+            .is_some_and(|delayed| delayed.until <= now)
+        {
+            let delayed = self.delayed.pop_front().unwrap();
+            self.send_client(delayed.packet, delayed.received_at).await;
+        }
+    }
+
+    // Monitors, captures and gaps only see the packets that make it to the server
+    async fn send_client(&mut self, packet: PacketBuf, received_at: DateTime<Local>) {
+        // Mutations would show up as gaps of their own, so the client's packet is checked
+        if gaps::enabled() {
+            gaps::check(true, &packet);
+        }
+
+        let mut packet = match &mut self.fuzzer {
+            Some(fuzzer) => fuzzer.client_packet(self.player_id.into(), packet),
+            None => packet,
+        };
+
+        let _ = self.tx.send(WSMessage::Packet {
+            session_id: self.id,
+            player_id: self.player_id as u32,
+            from: "Client".to_string(),
+            buf: packet.clone(),
+        });
+        if let Some(capture) = &mut self.capture {
+            capture.packet(self.player_id.into(), true, &packet);
+        }
+
+        // Mutations can change the action and family too
+        let known = known(&packet);
+        // Unknown packets are past Init too, so they carry a sequence number as well
+        let sequenced = known.is_none_or(|(_, family)| family != PacketFamily::Init);
+        if sequenced && packet.len() > 2 {
+            packet[2] = self.sequencer.get_sequence_bytes()[0];
+        }
+
+        let (action, family) = match known {
+            Some(known) => known,
+            None => {
+                let mut buf = packet;
+                self.server_bus.packet_processor.encode(&mut buf);
+                self.server_bus.send_raw(buf).await.unwrap();
+                self.latency
+                    .relayed(received_at, self.server_bus.timestamp());
+                return;
+            }
+        };
+
+        let reader = StreamReader::new(&packet[2..]);
+        let buf = reader.get_vec(reader.remaining());
+
+        self.server_bus.send(action, family, buf).await.unwrap();
+        self.latency
+            .relayed(received_at, self.server_bus.timestamp());
+        self.latency
+            .request_sent(true, family, action, self.server_bus.timestamp());
+
+        // Only chat that reached the server is logged
+        if family == PacketFamily::Talk && chat::enabled() {
+            let payload = packet.get(3..).unwrap_or_default();
+            if let Some(talk) = chat::client_talk(action, payload) {
+                self.chat(talk, true);
+            }
+        }
+    }
+
+    // Follows the client through the handshake, login and character selection, turning it
+    // away if it's banned. The packet is rewritten when its version is mapped
+    async fn inspect_client(
+        &mut self,
+        action: PacketAction,
+        family: PacketFamily,
+        packet: &mut PacketBuf,
+    ) -> Flow {
+        if family == PacketFamily::Init
+            && action == PacketAction::Init
+            && !self.handshake.complete()
+        {
+            self.handshake.client_init(&packet[2..]);
+            let span = Span::current();
+            if let Some(version) = &self.handshake.version {
+                span.record("version", version.as_str());
+            }
+            if let Some(hdid) = &self.handshake.hdid {
+                span.record("hdid", hdid.as_str());
+                tracking::hdid(self.id, hdid);
+            }
+
+            if let Some(ban) = self.handshake.hdid.as_deref().and_then(bans::check_hdid) {
+                if self.enforce(ban, false).await == Flow::Close {
+                    return Flow::Close;
+                }
+            }
+
+            if let Some(mapping) = self
+                .handshake
+                .version
+                .as_deref()
+                .and_then(versions::mapping)
+            {
+                match versions::rewrite_init(packet, &mapping.server) {
+                    Some(rewritten) => {
+                        info!(
+                            "Rewriting client version {} to {}",
+                            mapping.client, mapping.server
+                        );
+                        *packet = rewritten;
+                        self.handshake.rewritten_version = Some(mapping.server.clone());
+                        self.adapter = mapping.adapter.as_ref().map(PathBuf::from);
+                    }
+                    None => warn!("Unable to rewrite client version {}", mapping.client),
+                }
+            }
+        }
+
+        if family == PacketFamily::Login && action == PacketAction::Request {
+            let mut request = client::login::Request::new();
+            request.deserialize(&StreamReader::new(packet.get(3..).unwrap_or_default()));
+            tracking::account(self.id, &request.username);
+
+            let ban = self
+                .pending_ban
+                .take()
+                .or_else(|| bans::check_account(&request.username));
+            self.account = Some(request.username);
+            if let Some(ban) = ban {
+                return self.enforce(ban, true).await;
+            }
+        }
+
+        if family == PacketFamily::Welcome && action == PacketAction::Request {
+            let mut request = client::welcome::Request::new();
+            request.deserialize(&StreamReader::new(packet.get(3..).unwrap_or_default()));
+            let ban = self
+                .characters
+                .iter()
+                .find(|(id, _)| *id == request.character_id)
+                .and_then(|(_, name)| bans::check_character(name));
+            if let Some(ban) = ban {
+                return self.enforce(ban, true).await;
+            }
+        }
+
+        Flow::Continue
+    }
+
+    // Turns the client away as the ban says. A login rejection waits until the client gets as
+    // far as logging in
+    async fn enforce(&mut self, ban: Ban, past_init: bool) -> Flow {
+        if ban.reply == BanReply::Login && !past_init {
+            self.pending_ban = Some(ban);
+            return Flow::Continue;
+        }
+
+        warn!("Turning client away: {}", ban);
+        let _ = self.tx.send(WSMessage::ConnectionRejected {
+            addr: self.addr.to_string(),
+            reason: ban.to_string(),
+        });
+
+        let reply = match ban.reply {
+            BanReply::Init if past_init => BanReply::Login,
+            reply => reply,
+        };
+        let result = match reply {
+            BanReply::Init => {
+                let packet = replies::init_banned(ban.is_permanent());
+                self.client_bus
+                    .send(PacketAction::Init, PacketFamily::Init, packet)
+                    .await
+            }
+            BanReply::Login => {
+                let packet = replies::login_banned();
+                self.client_bus
+                    .send(PacketAction::Reply, PacketFamily::Login, packet)
+                    .await
+            }
+            BanReply::Disconnect => Ok(()),
+        };
+        if let Err(e) = result {
+            debug!("Failed to send ban reply: {}", e);
+        }
+
+        Flow::Close
+    }
+
+    fn chat(&self, talk: Talk, from_client: bool) {
+        let (sender, receiver) = if from_client {
+            (self.character.clone(), talk.name)
+        } else if talk.channel == Channel::Private {
+            (talk.name, self.character.clone())
+        } else {
+            (talk.name, None)
+        };
+
+        let message = ChatMessage {
+            time: Local::now(),
+            session_id: self.id,
+            channel: talk.channel,
+            // Only known for the sender's own session
+            map_id: if from_client { self.map_id } else { None },
+            sender,
+            receiver,
+            message: talk.message,
+        };
+
+        if chat::log(&message, from_client) {
+            let _ = self.tx.send(WSMessage::Chat(message));
+        }
+    }
+
+    fn server_init(&mut self, data: &InitData) {
+        if let Some(player_id) = self.handshake.server_init(data) {
+            self.player_id = player_id;
+            Span::current().record("player_id", self.player_id);
+
+            let _ = self.tx.send(WSMessage::SetPlayerId {
+                session_id: self.id,
+                player_id: self.player_id.into(),
+            });
+            let _ = self.tx.send(WSMessage::PlayerInfo {
+                session_id: self.id,
+                player_id: self.player_id.into(),
+                addr: self.addr.to_string(),
+                character: None,
+            });
+        }
+
+        if let InitData::Ok(reply_ok) = data {
+            // eo's sequencer panics on a start below zero, so one is left at the last start
+            let [seq1, seq2] = reply_ok.seq_bytes;
+            if i32::from(seq1) * 7 + i32::from(seq2) >= 13 {
+                self.sequencer.set_init_sequence(seq1.into(), seq2.into());
+            } else {
+                warn!("Server sent an invalid sequence start: {} {}", seq1, seq2);
+            }
+            self.server_bus
+                .packet_processor
+                .set_multiples(reply_ok.encode_multiple, reply_ok.decode_multiple);
+            self.client_bus
+                .packet_processor
+                .set_multiples(reply_ok.decode_multiple, reply_ok.encode_multiple);
+        }
+
+        match &self.handshake.outcome {
+            Some(HandshakeOutcome::Ok) if self.handshake.challenge_verified == Some(false) => {
+                warn!("Server answered the client's challenge wrongly");
+            }
+            Some(HandshakeOutcome::Ok) => {}
+            Some(outcome) => info!("Init refused: {:?}", outcome),
+            None => {}
+        }
+
+        let _ = self.tx.send(WSMessage::Handshake {
+            session_id: self.id,
+            player_id: self.player_id.into(),
+            handshake: self.handshake.clone(),
+        });
+    }
+
+    fn reply_received(
+        &mut self,
+        from_client: bool,
+        family: PacketFamily,
+        action: PacketAction,
+        received_at: DateTime<Local>,
+    ) {
+        let measurement =
+            match self
+                .latency
+                .reply_received(from_client, family, action, received_at)
+            {
+                Some(measurement) => measurement,
+                None => return,
+            };
+        debug!(
+            request = %measurement.request,
+            response_ms = measurement.response.num_milliseconds(),
+            "Reply received"
+        );
+
+        let _ = self.tx.send(WSMessage::Latency {
+            session_id: self.id,
+            player_id: self.player_id as u32,
+            request: measurement.request,
+            response_ms: measurement.response.num_milliseconds(),
+            client_rtt_ms: measurement.client_rtt.map(|d| d.num_milliseconds()),
+            average_relay_us: self
+                .latency
+                .average_relay()
+                .num_microseconds()
+                .unwrap_or_default(),
+        });
+    }
+
+    async fn forward_server(&mut self, packet: PacketBuf, received_at: DateTime<Local>) {
+        let _ = self.tx.send(WSMessage::Packet {
+            session_id: self.id,
+            player_id: self.player_id as u32,
+            from: "Server".to_string(),
+            buf: packet.clone(),
+        });
+        if let Some(capture) = &mut self.capture {
+            capture.packet(self.player_id.into(), false, &packet);
+        }
+        if gaps::enabled() {
+            gaps::check(false, &packet);
+        }
+        if let Some(fuzzer) = &mut self.fuzzer {
+            fuzzer.server_packet(self.player_id.into(), &packet);
+        }
+
+        let action = packet.first().copied().and_then(PacketAction::from_byte);
+        let family = packet.get(1).copied().and_then(PacketFamily::from_byte);
+        if let (Some(action), Some(family)) = (action, family) {
+            self.reply_received(false, family, action, received_at);
+
+            debug!(
+                family = ?family,
+                action = ?action,
+                len = packet.len(),
+                "From server: {:?}",
+                packet
+            );
+
+            let reader = StreamReader::new(&packet[2..]);
+            let buf = reader.get_vec(reader.remaining());
+            reader.reset();
+
+            match family {
+                PacketFamily::Init if action == PacketAction::Init => {
+                    let mut reply = Init::new();
+                    reply.deserialize(&reader);
+                    debug!("{:?}", reply);
+
+                    // Init_Init replies after the handshake are file transfers
+                    if !self.handshake.complete() {
+                        self.server_init(&reply.data);
+                    }
+                }
+                PacketFamily::Connection if action == PacketAction::Player => {
+                    let mut ping = connection::Player::new();
+                    ping.deserialize(&reader);
+                    if ping.seq1 >= ping.seq2.into() {
+                        self.sequencer
+                            .set_new_initial_sequence_number(ping.seq1.into(), ping.seq2.into());
+                    } else {
+                        warn!(
+                            "Server sent an invalid sequence start: {} {}",
+                            ping.seq1, ping.seq2
+                        );
+                    }
+                }
+                PacketFamily::Login if action == PacketAction::Reply => {
+                    let mut reply = login::Reply::new();
+                    reply.deserialize(&reader);
+
+                    if let login::ReplyData::Ok(reply_ok) = reply.data {
+                        self.characters = reply_ok
+                            .character_list
+                            .characters
+                            .into_iter()
+                            .map(|character| (character.id, character.name))
+                            .collect();
+                    }
+                }
+                PacketFamily::Warp if action == PacketAction::Agree && chat::enabled() => {
+                    if let Some(map_id) = chat::warp_map(&buf) {
+                        self.map_id = Some(map_id);
+                    }
+                }
+                PacketFamily::Talk if chat::enabled() => {
+                    if let Some(talk) = chat::server_talk(action, &buf) {
+                        self.chat(talk, false);
+                    }
+                }
+                PacketFamily::Welcome if action == PacketAction::Reply => {
+                    let mut reply = welcome::Reply::new();
+                    reply.deserialize(&reader);
+
+                    if let welcome::ReplyData::SelectCharacter(reply_select_character) = reply.data
+                    {
+                        Span::current().record("character", reply_select_character.name.as_str());
+                        self.character = Some(reply_select_character.name.clone());
+                        self.map_id = Some(reply_select_character.map_id);
+                        tracking::character(self.id, &reply_select_character.name);
+                        let _ = self.tx.send(WSMessage::PlayerInfo {
+                            session_id: self.id,
+                            player_id: self.player_id.into(),
+                            addr: self.addr.to_string(),
+                            character: Some(reply_select_character.name.clone()),
+                        });
+                    }
+                }
+                _ => {}
+            }
+
+            self.client_bus.send(action, family, buf).await.unwrap();
+            self.latency
+                .request_sent(false, family, action, self.client_bus.timestamp());
+        } else {
+            let mut buf = packet;
+            self.client_bus.packet_processor.encode(&mut buf);
+            self.client_bus.send_raw(buf).await.unwrap();
+        }
+        self.latency
+            .relayed(received_at, self.client_bus.timestamp());
+    }
+}
+
+fn known(packet: &[u8]) -> Option<(PacketAction, PacketFamily)> {
+    match (
+        packet.first().copied().and_then(PacketAction::from_byte),
+        packet.get(1).copied().and_then(PacketFamily::from_byte),
+    ) {
+        (Some(action), Some(family)) => Some((action, family)),
+        _ => None,
+    }
+}
+
+// Waits for the client's Init request so the player is shown why they can't connect instead
+// of the connection just dropping. When too many are already waiting it just drops
+pub async fn reject<S: Stream + 'static>(client_socket: S, rejection: Rejection) {
+    let _permit = match REJECTIONS.try_acquire() {
+        Ok(permit) => permit,
+        Err(_) => {
+            debug!("Too many rejections waiting, closing the connection");
+            return;
+        }
+    };
+
+    let mut client_bus = Bus::new(client_socket, "Client".to_string());
+
+    let init_received = timeout(REJECT_TIMEOUT, async {
+        loop {
+            match client_bus.recv().await {
+                Some(Ok(_)) => return true,
+                Some(Err(_)) => return false,
+                None => {}
+            }
+        }
+    })
+    .await;
+
+    if let Ok(true) = init_received {
+        let _ = client_bus
+            .send(
+                PacketAction::Init,
+                PacketFamily::Init,
+                replies::init_banned(rejection.is_permanent()),
+            )
+            .await;
+    }
+}
@@ -1,21 +1,400 @@
+use std::{collections::HashMap, path::Path};
+
 use config::{Config, ConfigError, File};
 
+use crate::versions;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocol {
+    V1,
+    V2,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListenerTls {
+    pub cert: String,
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpstreamTls {
+    // Name to verify the server certificate against, defaults to the server host
+    pub domain: Option<String>,
+    // Trusted roots in PEM format, defaults to the Mozilla root store
+    pub ca: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Server {
     pub host: String,
     pub port: String,
+    pub proxy_protocol: Option<ProxyProtocol>,
+    pub tls: Option<UpstreamTls>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Proxy {
     pub host: String,
     pub port: String,
+    #[serde(default)]
+    pub accept_proxy_protocol: bool,
+    pub websocket_port: Option<String>,
+    pub tls: Option<ListenerTls>,
+    // Where monitors connect, always on localhost
+    #[serde(default = "default_monitor_port")]
+    pub monitor_port: String,
+}
+
+fn default_monitor_port() -> String {
+    "9001".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Log {
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    #[serde(default)]
+    pub json: bool,
+    pub directory: Option<String>,
+    #[serde(default = "default_log_rotation")]
+    pub rotation: String,
+    #[serde(default = "default_log_file_prefix")]
+    pub file_prefix: String,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            json: false,
+            directory: None,
+            rotation: default_log_rotation(),
+            file_prefix: default_log_file_prefix(),
+        }
+    }
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_rotation() -> String {
+    "daily".to_string()
+}
+
+fn default_log_file_prefix() -> String {
+    "eoproxy.log".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitAction {
+    Drop,
+    Delay,
+    Disconnect,
+    Flag,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FamilyLimit {
+    pub packets_per_second: f64,
+    pub burst: u32,
+    pub action: Option<LimitAction>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RateLimit {
+    #[serde(default)]
+    pub enabled: bool,
+    pub packets_per_second: f64,
+    pub burst: u32,
+    pub action: LimitAction,
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+    #[serde(default)]
+    pub families: HashMap<String, FamilyLimit>,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            packets_per_second: 30.0,
+            burst: 60,
+            action: LimitAction::Drop,
+            max_delay_ms: default_max_delay_ms(),
+            families: HashMap::new(),
+        }
+    }
+}
+
+fn default_max_delay_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Access {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    #[serde(default)]
+    pub max_sessions: usize,
+    #[serde(default)]
+    pub max_connections_per_ip: usize,
+    #[serde(default)]
+    pub connections_per_minute: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Scripting {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_scripts_directory")]
+    pub directory: String,
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+impl Default for Scripting {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: default_scripts_directory(),
+            poll_interval_ms: default_poll_interval_ms(),
+        }
+    }
+}
+
+fn default_scripts_directory() -> String {
+    "scripts".to_string()
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Capture {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_captures_directory")]
+    pub directory: String,
+    pub filter: Option<String>,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: default_captures_directory(),
+            filter: None,
+        }
+    }
+}
+
+fn default_captures_directory() -> String {
+    "captures".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProtocolGaps {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_gaps_report")]
+    pub report: String,
+    #[serde(default = "default_gaps_samples")]
+    pub samples: usize,
+    #[serde(default = "default_gaps_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl Default for ProtocolGaps {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            report: default_gaps_report(),
+            samples: default_gaps_samples(),
+            interval_secs: default_gaps_interval_secs(),
+        }
+    }
+}
+
+fn default_gaps_report() -> String {
+    "protocol_gaps.json".to_string()
+}
+
+fn default_gaps_samples() -> usize {
+    5
+}
+
+fn default_gaps_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Fuzz {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_fuzz_rate")]
+    pub rate: f64,
+    #[serde(default = "default_hang_timeout_ms")]
+    pub hang_timeout_ms: u64,
+    #[serde(default = "default_fuzz_directory")]
+    pub directory: String,
+    pub seed: Option<u64>,
+}
+
+impl Default for Fuzz {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rate: default_fuzz_rate(),
+            hang_timeout_ms: default_hang_timeout_ms(),
+            directory: default_fuzz_directory(),
+            seed: None,
+        }
+    }
+}
+
+fn default_fuzz_rate() -> f64 {
+    0.05
+}
+
+fn default_hang_timeout_ms() -> u64 {
+    10000
+}
+
+fn default_fuzz_directory() -> String {
+    "fuzz".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Tracking {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_tracking_database")]
+    pub database: String,
+}
+
+impl Default for Tracking {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            database: default_tracking_database(),
+        }
+    }
+}
+
+fn default_tracking_database() -> String {
+    "tracking.db".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Bans {
+    #[serde(default = "default_bans_file")]
+    pub file: String,
+}
+
+impl Default for Bans {
+    fn default() -> Self {
+        Self {
+            file: default_bans_file(),
+        }
+    }
+}
+
+fn default_bans_file() -> String {
+    "bans.json".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Chat {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_chat_directory")]
+    pub directory: String,
+}
+
+impl Default for Chat {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: default_chat_directory(),
+        }
+    }
+}
+
+fn default_chat_directory() -> String {
+    "chat".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VersionMapping {
+    // Versions are written major.minor.patch, as in 0.0.28
+    pub client: String,
+    pub server: String,
+    #[serde(default)]
+    pub adapter: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Versions {
+    #[serde(default)]
+    pub mappings: Vec<VersionMapping>,
+}
+
+// Monitors have to present the token to connect, and browsers have to be on an allowed origin
+#[derive(Debug, Deserialize)]
+pub struct Monitor {
+    pub token: Option<String>,
+    #[serde(default = "default_monitor_origins")]
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self {
+            token: None,
+            allowed_origins: default_monitor_origins(),
+        }
+    }
+}
+
+// Where the web UI's dev server runs
+fn default_monitor_origins() -> Vec<String> {
+    vec!["http://localhost:3000".to_string()]
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub server: Server,
     pub proxy: Proxy,
+    #[serde(default)]
+    pub log: Log,
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    #[serde(default)]
+    pub access: Access,
+    #[serde(default)]
+    pub scripting: Scripting,
+    #[serde(default)]
+    pub capture: Capture,
+    #[serde(default)]
+    pub protocol_gaps: ProtocolGaps,
+    #[serde(default)]
+    pub fuzz: Fuzz,
+    #[serde(default)]
+    pub versions: Versions,
+    #[serde(default)]
+    pub tracking: Tracking,
+    #[serde(default)]
+    pub bans: Bans,
+    #[serde(default)]
+    pub chat: Chat,
+    #[serde(default)]
+    pub monitor: Monitor,
 }
 
 impl Settings {
@@ -25,6 +404,39 @@ impl Settings {
             .add_source(File::with_name("Config.local.toml").required(false))
             .build()?;
 
-        s.try_deserialize()
+        let settings: Self = s.try_deserialize()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&self.fuzz.rate) {
+            return Err(ConfigError::Message(format!(
+                "fuzz.rate must be between 0 and 1, not {}",
+                self.fuzz.rate
+            )));
+        }
+
+        for mapping in &self.versions.mappings {
+            for version in [&mapping.client, &mapping.server] {
+                if versions::parse(version).is_none() {
+                    return Err(ConfigError::Message(format!(
+                        "versions.mappings: {:?} isn't a version, expected major.minor.patch",
+                        version
+                    )));
+                }
+            }
+
+            if let Some(adapter) = &mapping.adapter {
+                if !Path::new(adapter).is_file() {
+                    return Err(ConfigError::Message(format!(
+                        "versions.mappings: adapter {:?} for client {} doesn't exist",
+                        adapter, mapping.client
+                    )));
+                }
+            }
+        }
+
+        Ok(())
     }
 }
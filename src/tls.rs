@@ -0,0 +1,100 @@
+use std::{fs::File, io::BufReader, sync::Arc};
+
+use lazy_static::lazy_static;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor, TlsConnector,
+};
+
+use crate::{
+    bus::Stream,
+    settings::{ListenerTls, UpstreamTls},
+    SETTINGS,
+};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+lazy_static! {
+    static ref ACCEPTOR: Option<TlsAcceptor> = SETTINGS.proxy.tls.as_ref().map(|tls| {
+        acceptor(tls).expect("Failed to load TLS certificate for the proxy listener")
+    });
+    static ref CONNECTOR: Option<TlsConnector> = SETTINGS.server.tls.as_ref().map(|tls| {
+        connector(tls).expect("Failed to load TLS settings for the server connection")
+    });
+}
+
+// Loads certificates up front so bad paths are reported at startup instead of on the first
+// connection
+pub fn init() {
+    lazy_static::initialize(&ACCEPTOR);
+    lazy_static::initialize(&CONNECTOR);
+}
+
+pub fn listener_enabled() -> bool {
+    ACCEPTOR.is_some()
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| format!("No private key found in {}", path).into())
+}
+
+fn acceptor(tls: &ListenerTls) -> Result<TlsAcceptor, Error> {
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(load_certs(&tls.cert)?, load_key(&tls.key)?)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn connector(tls: &UpstreamTls) -> Result<TlsConnector, Error> {
+    let mut roots = RootCertStore::empty();
+    match &tls.ca {
+        Some(path) => {
+            for cert in load_certs(path)? {
+                roots.add(cert)?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+pub async fn accept(socket: TcpStream) -> std::io::Result<TlsStream<TcpStream>> {
+    ACCEPTOR
+        .as_ref()
+        .expect("TLS is not enabled on the proxy listener")
+        .accept(socket)
+        .await
+}
+
+// Upstream TLS is optional, so the socket is passed through untouched when it's disabled
+pub async fn connect(socket: TcpStream) -> std::io::Result<Box<dyn Stream>> {
+    let (connector, tls) = match (CONNECTOR.as_ref(), SETTINGS.server.tls.as_ref()) {
+        (Some(connector), Some(tls)) => (connector, tls),
+        _ => return Ok(Box::new(socket)),
+    };
+
+    let domain = tls
+        .domain
+        .clone()
+        .unwrap_or_else(|| SETTINGS.server.host.clone());
+    let domain = ServerName::try_from(domain)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    Ok(Box::new(connector.connect(domain, socket).await?))
+}
@@ -0,0 +1,357 @@
+use std::{collections::HashMap, net::IpAddr, thread};
+
+use chrono::Local;
+use lazy_static::lazy_static;
+use rusqlite::{params, Connection, OpenFlags};
+use tokio::{
+    sync::mpsc::{self, UnboundedSender},
+    task,
+};
+
+use crate::SETTINGS;
+
+// Keeps a record of every connection in a SQLite database: where it came from, the HDID and
+// account it used, and the characters it played. Moderators query it from the monitor to
+// find alts and ban evasion
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS connections (
+        id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL,
+        ip TEXT NOT NULL,
+        hdid TEXT,
+        account TEXT,
+        connected_at TEXT NOT NULL,
+        disconnected_at TEXT
+    );
+    CREATE TABLE IF NOT EXISTS characters (
+        connection_id INTEGER NOT NULL REFERENCES connections (id),
+        name TEXT NOT NULL,
+        selected_at TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS connections_ip ON connections (ip);
+    CREATE INDEX IF NOT EXISTS connections_hdid ON connections (hdid);
+    CREATE INDEX IF NOT EXISTS connections_account ON connections (account COLLATE NOCASE);
+    CREATE INDEX IF NOT EXISTS characters_connection ON characters (connection_id);
+    CREATE INDEX IF NOT EXISTS characters_name ON characters (name COLLATE NOCASE);
+";
+
+const DEFAULT_LIMIT: u32 = 100;
+
+lazy_static! {
+    static ref WRITER: Option<UnboundedSender<Write>> = start_writer();
+}
+
+// Sessions hand their writes to a thread of their own, so they never wait on the database
+enum Write {
+    Connected {
+        session_id: u32,
+        ip: IpAddr,
+        at: String,
+    },
+    Hdid {
+        session_id: u32,
+        hdid: String,
+    },
+    Account {
+        session_id: u32,
+        account: String,
+    },
+    Character {
+        session_id: u32,
+        name: String,
+        at: String,
+    },
+    Disconnected {
+        session_id: u32,
+        at: String,
+    },
+}
+
+fn open() -> rusqlite::Result<Connection> {
+    let connection = Connection::open(&SETTINGS.tracking.database)?;
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    connection.execute_batch(SCHEMA)?;
+    Ok(connection)
+}
+
+fn start_writer() -> Option<UnboundedSender<Write>> {
+    if !enabled() {
+        return None;
+    }
+
+    let connection = match open() {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!(
+                "Failed to open tracking database {}: {}",
+                SETTINGS.tracking.database, e
+            );
+            return None;
+        }
+    };
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    thread::spawn(move || {
+        // The row of each connected session
+        let mut rows: HashMap<u32, i64> = HashMap::new();
+        while let Some(write) = rx.blocking_recv() {
+            // Failing to record shouldn't affect the session, so writes only log errors
+            if let Err(e) = apply(&connection, &mut rows, write) {
+                error!("Failed to write to tracking database: {}", e);
+            }
+        }
+    });
+    Some(tx)
+}
+
+fn apply(
+    connection: &Connection,
+    rows: &mut HashMap<u32, i64>,
+    write: Write,
+) -> rusqlite::Result<()> {
+    match write {
+        Write::Connected { session_id, ip, at } => {
+            connection.execute(
+                "INSERT INTO connections (session_id, ip, connected_at) VALUES (?1, ?2, ?3)",
+                params![session_id, ip.to_string(), at],
+            )?;
+            rows.insert(session_id, connection.last_insert_rowid());
+        }
+        Write::Hdid { session_id, hdid } => {
+            if let Some(id) = rows.get(&session_id) {
+                connection.execute(
+                    "UPDATE connections SET hdid = ?1 WHERE id = ?2",
+                    params![hdid, id],
+                )?;
+            }
+        }
+        Write::Account {
+            session_id,
+            account,
+        } => {
+            if let Some(id) = rows.get(&session_id) {
+                connection.execute(
+                    "UPDATE connections SET account = ?1 WHERE id = ?2",
+                    params![account, id],
+                )?;
+            }
+        }
+        Write::Character {
+            session_id,
+            name,
+            at,
+        } => {
+            if let Some(id) = rows.get(&session_id) {
+                connection.execute(
+                    "INSERT INTO characters (connection_id, name, selected_at) VALUES (?1, ?2, ?3)",
+                    params![id, name, at],
+                )?;
+            }
+        }
+        Write::Disconnected { session_id, at } => {
+            if let Some(id) = rows.remove(&session_id) {
+                connection.execute(
+                    "UPDATE connections SET disconnected_at = ?1 WHERE id = ?2",
+                    params![at, id],
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    SETTINGS.tracking.enabled
+}
+
+fn now() -> String {
+    Local::now().to_rfc3339()
+}
+
+fn record(write: Write) {
+    if let Some(writer) = WRITER.as_ref() {
+        let _ = writer.send(write);
+    }
+}
+
+pub fn connected(session_id: u32, ip: IpAddr) {
+    record(Write::Connected {
+        session_id,
+        ip,
+        at: now(),
+    });
+}
+
+pub fn hdid(session_id: u32, hdid: &str) {
+    record(Write::Hdid {
+        session_id,
+        hdid: hdid.to_string(),
+    });
+}
+
+pub fn account(session_id: u32, account: &str) {
+    record(Write::Account {
+        session_id,
+        account: account.to_string(),
+    });
+}
+
+pub fn character(session_id: u32, name: &str) {
+    record(Write::Character {
+        session_id,
+        name: name.to_string(),
+        at: now(),
+    });
+}
+
+pub fn disconnected(session_id: u32) {
+    record(Write::Disconnected {
+        session_id,
+        at: now(),
+    });
+}
+
+// Queries open a connection of their own on a blocking thread, so they neither wait on
+// writes nor hold up the monitor
+async fn read<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+) -> Result<T, String> {
+    if WRITER.is_none() {
+        return Err("Tracking is disabled".to_string());
+    }
+
+    task::spawn_blocking(move || {
+        let connection = Connection::open_with_flags(
+            &SETTINGS.tracking.database,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        f(&connection)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+// Every field given has to match. Account and character names match regardless of case
+#[derive(Debug, Default, Deserialize)]
+pub struct Query {
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub hdid: Option<String>,
+    #[serde(default)]
+    pub account: Option<String>,
+    #[serde(default)]
+    pub character: Option<String>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionRecord {
+    pub id: i64,
+    pub session_id: u32,
+    pub ip: String,
+    pub hdid: Option<String>,
+    pub account: Option<String>,
+    pub characters: Vec<String>,
+    pub connected_at: String,
+    pub disconnected_at: Option<String>,
+}
+
+// An account that shares an IP or HDID with the one searched for
+#[derive(Debug, Serialize)]
+pub struct Alt {
+    pub account: String,
+    // ip or hdid
+    pub shared: String,
+    pub value: String,
+    pub last_seen: String,
+}
+
+// Newest first
+pub async fn find(query: Query) -> Result<Vec<ConnectionRecord>, String> {
+    read(move |connection| {
+        let mut statement = connection.prepare(
+            "SELECT id, session_id, ip, hdid, account, connected_at, disconnected_at
+             FROM connections c
+             WHERE (?1 IS NULL OR ip = ?1)
+               AND (?2 IS NULL OR hdid = ?2)
+               AND (?3 IS NULL OR account = ?3 COLLATE NOCASE)
+               AND (?4 IS NULL OR EXISTS (
+                   SELECT 1 FROM characters
+                   WHERE connection_id = c.id AND name = ?4 COLLATE NOCASE))
+             ORDER BY id DESC
+             LIMIT ?5",
+        )?;
+        let mut characters = connection
+            .prepare("SELECT name FROM characters WHERE connection_id = ?1 ORDER BY rowid")?;
+
+        let rows = statement.query_map(
+            params![
+                query.ip,
+                query.hdid,
+                query.account,
+                query.character,
+                query.limit.unwrap_or(DEFAULT_LIMIT)
+            ],
+            |row| {
+                Ok(ConnectionRecord {
+                    id: row.get(0)?,
+                    session_id: row.get(1)?,
+                    ip: row.get(2)?,
+                    hdid: row.get(3)?,
+                    account: row.get(4)?,
+                    characters: Vec::new(),
+                    connected_at: row.get(5)?,
+                    disconnected_at: row.get(6)?,
+                })
+            },
+        )?;
+
+        let mut records = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        for record in &mut records {
+            record.characters = characters
+                .query_map([record.id], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+        }
+        Ok(records)
+    })
+    .await
+}
+
+// Other accounts that have connected from any IP or HDID the account has used
+pub async fn alts(account: String) -> Result<Vec<Alt>, String> {
+    read(move |connection| {
+        let mut statement = connection.prepare(
+            "SELECT other.account, 'ip', other.ip, MAX(other.connected_at)
+             FROM connections mine
+             JOIN connections other ON other.ip = mine.ip
+             WHERE mine.account = ?1 COLLATE NOCASE
+               AND other.account IS NOT NULL
+               AND other.account <> mine.account COLLATE NOCASE
+             GROUP BY other.account, other.ip
+             UNION ALL
+             SELECT other.account, 'hdid', other.hdid, MAX(other.connected_at)
+             FROM connections mine
+             JOIN connections other ON other.hdid = mine.hdid
+             WHERE mine.account = ?1 COLLATE NOCASE
+               AND other.account IS NOT NULL
+               AND other.account <> mine.account COLLATE NOCASE
+             GROUP BY other.account, other.hdid
+             ORDER BY 4 DESC",
+        )?;
+
+        let rows = statement.query_map([&account], |row| {
+            Ok(Alt {
+                account: row.get(0)?,
+                shared: row.get(1)?,
+                value: row.get(2)?,
+                last_seen: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    })
+    .await
+}
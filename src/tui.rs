@@ -0,0 +1,495 @@
+use std::{
+    collections::VecDeque,
+    io::{stdout, Stdout},
+    panic,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        cursor::Show,
+        event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    Frame, Terminal,
+};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    mpsc,
+};
+
+use crate::{decode, WSMessage};
+
+const MAX_PACKETS: usize = 10_000;
+const TICK: Duration = Duration::from_millis(250);
+
+struct SessionEntry {
+    session_id: u32,
+    player_id: u32,
+    addr: String,
+    character: Option<String>,
+    connected: bool,
+}
+
+struct PacketEntry {
+    id: u64,
+    session_id: u32,
+    player_id: u32,
+    from_client: bool,
+    name: String,
+    buf: Vec<u8>,
+    time: DateTime<Local>,
+}
+
+#[derive(PartialEq)]
+enum Focus {
+    Sessions,
+    Packets,
+    Filter,
+}
+
+struct App {
+    sessions: Vec<SessionEntry>,
+    packets: VecDeque<PacketEntry>,
+    next_packet_id: u64,
+    // Indexes into packets of the ones shown, worked out once per frame
+    visible: Vec<usize>,
+    // The selected packet's decoded JSON, kept until another packet is selected
+    decoded: Option<(u64, String)>,
+    session_state: ListState,
+    packet_state: ListState,
+    focus: Focus,
+    filter: String,
+    follow: bool,
+    quit: bool,
+}
+
+impl App {
+    fn new() -> Self {
+        let mut session_state = ListState::default();
+        session_state.select(Some(0));
+        Self {
+            sessions: Vec::new(),
+            packets: VecDeque::new(),
+            next_packet_id: 0,
+            visible: Vec::new(),
+            decoded: None,
+            session_state,
+            packet_state: ListState::default(),
+            focus: Focus::Packets,
+            filter: String::new(),
+            follow: true,
+            quit: false,
+        }
+    }
+
+    fn handle_message(&mut self, message: WSMessage) {
+        match message {
+            WSMessage::AddPlayer { session_id } => {
+                self.sessions.push(SessionEntry {
+                    session_id,
+                    player_id: 0,
+                    addr: String::new(),
+                    character: None,
+                    connected: true,
+                });
+            }
+            WSMessage::SetPlayerId {
+                session_id,
+                player_id,
+            } => {
+                if let Some(session) = self.session_mut(session_id) {
+                    session.player_id = player_id;
+                }
+                for packet in self
+                    .packets
+                    .iter_mut()
+                    .filter(|p| p.session_id == session_id)
+                {
+                    packet.player_id = player_id;
+                }
+            }
+            WSMessage::PlayerInfo {
+                session_id,
+                addr,
+                character,
+                ..
+            } => {
+                if let Some(session) = self.session_mut(session_id) {
+                    session.addr = addr;
+                    session.character = character;
+                }
+            }
+            WSMessage::RemovePlayer { session_id, .. } => {
+                if let Some(session) = self.session_mut(session_id) {
+                    session.connected = false;
+                }
+            }
+            WSMessage::Packet {
+                session_id,
+                player_id,
+                from,
+                buf,
+            } => {
+                if self.packets.len() == MAX_PACKETS {
+                    self.packets.pop_front();
+                }
+                self.packets.push_back(PacketEntry {
+                    id: self.next_packet_id,
+                    session_id,
+                    player_id,
+                    from_client: from == "Client",
+                    name: decode::packet_name(&buf),
+                    buf,
+                    time: Local::now(),
+                });
+                self.next_packet_id += 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn session_mut(&mut self, session_id: u32) -> Option<&mut SessionEntry> {
+        self.sessions
+            .iter_mut()
+            .find(|s| s.session_id == session_id)
+    }
+
+    // Entry 0 of the session list shows packets from every session
+    fn selected_session(&self) -> Option<u32> {
+        match self.session_state.selected() {
+            Some(0) | None => None,
+            Some(index) => self.sessions.get(index - 1).map(|s| s.session_id),
+        }
+    }
+
+    fn matches_filter(&self, packet: &PacketEntry) -> bool {
+        if let Some(session_id) = self.selected_session() {
+            if packet.session_id != session_id {
+                return false;
+            }
+        }
+
+        self.filter
+            .split_whitespace()
+            .all(|term| match term.to_lowercase().as_str() {
+                "client" => packet.from_client,
+                "server" => !packet.from_client,
+                term => packet.name.to_lowercase().contains(term),
+            })
+    }
+
+    fn update_visible(&mut self) {
+        self.visible = (0..self.packets.len())
+            .filter(|&i| self.matches_filter(&self.packets[i]))
+            .collect();
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        if self.focus == Focus::Filter {
+            match key.code {
+                KeyCode::Enter => self.focus = Focus::Packets,
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.focus = Focus::Packets;
+                }
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Char(c) => self.filter.push(c),
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Char('/') => self.focus = Focus::Filter,
+            KeyCode::Char('f') => self.follow = !self.follow,
+            KeyCode::Esc => self.filter.clear(),
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Sessions => Focus::Packets,
+                    _ => Focus::Sessions,
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-20),
+            KeyCode::PageDown => self.move_selection(20),
+            _ => {}
+        }
+    }
+
+    fn move_selection(&mut self, amount: i32) {
+        let (state, len) = match self.focus {
+            Focus::Sessions => (&mut self.session_state, self.sessions.len() + 1),
+            _ => {
+                self.follow = false;
+                (&mut self.packet_state, self.visible.len())
+            }
+        };
+
+        if len == 0 {
+            return;
+        }
+
+        let current = state.selected().unwrap_or(0) as i32;
+        let next = (current + amount).clamp(0, len as i32 - 1);
+        state.select(Some(next as usize));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        self.update_visible();
+
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(1)])
+            .split(frame.area());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(25), Constraint::Percentage(75)])
+            .split(rows[0]);
+        let panes = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(columns[1]);
+
+        self.draw_sessions(frame, columns[0]);
+        self.draw_packets(frame, panes[0]);
+        self.draw_details(frame, panes[1]);
+        self.draw_status(frame, rows[1]);
+    }
+
+    fn draw_sessions(&mut self, frame: &mut Frame, area: Rect) {
+        let mut items = vec![ListItem::new("All sessions")];
+        items.extend(self.sessions.iter().map(|session| {
+            let style = if session.connected {
+                Style::default()
+            } else {
+                Style::default().fg(Color::DarkGray)
+            };
+            ListItem::new(format!(
+                "{} {} {}",
+                session.player_id,
+                session.character.as_deref().unwrap_or("-"),
+                session.addr
+            ))
+            .style(style)
+        }));
+
+        let list = List::new(items)
+            .block(block("Sessions", self.focus == Focus::Sessions))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.session_state);
+    }
+
+    fn draw_packets(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .visible
+            .iter()
+            .map(|&i| {
+                let packet = &self.packets[i];
+                let (icon, color) = if packet.from_client {
+                    ("C ➡ S", Color::Cyan)
+                } else {
+                    ("S ➡ C", Color::Yellow)
+                };
+                ListItem::new(Line::from(vec![
+                    Span::raw(format!("{} ", packet.time.format("%H:%M:%S%.3f"))),
+                    Span::styled(icon, Style::default().fg(color)),
+                    Span::raw(format!(
+                        " {:>5} {} ({} bytes)",
+                        packet.player_id,
+                        packet.name,
+                        packet.buf.len()
+                    )),
+                ]))
+            })
+            .collect();
+
+        if self.follow && !items.is_empty() {
+            self.packet_state.select(Some(items.len() - 1));
+        }
+
+        let title = format!("Packets ({})", items.len());
+        let list = List::new(items)
+            .block(block(&title, self.focus == Focus::Packets))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.packet_state);
+    }
+
+    fn draw_details(&mut self, frame: &mut Frame, area: Rect) {
+        let selected = self
+            .packet_state
+            .selected()
+            .and_then(|i| self.visible.get(i))
+            .map(|&i| &self.packets[i]);
+        let packet = match selected {
+            Some(packet) => packet,
+            None => {
+                frame.render_widget(Paragraph::new("").block(block("Packet", false)), area);
+                return;
+            }
+        };
+
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(78), Constraint::Min(0)])
+            .split(area);
+
+        frame.render_widget(
+            Paragraph::new(hex_dump(&packet.buf)).block(block("Hex", false)),
+            columns[0],
+        );
+
+        let decoded = match &self.decoded {
+            Some((id, decoded)) if *id == packet.id => decoded,
+            _ => {
+                let decoded = match decode::decode(packet.from_client, &packet.buf) {
+                    Some(value) => serde_json::to_string_pretty(&value).unwrap_or_default(),
+                    None => "Unable to decode packet".to_string(),
+                };
+                &self.decoded.insert((packet.id, decoded)).1
+            }
+        };
+        frame.render_widget(
+            Paragraph::new(decoded.as_str())
+                .wrap(Wrap { trim: false })
+                .block(block(&packet.name, false)),
+            columns[1],
+        );
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let text = if self.focus == Focus::Filter {
+            format!("Filter: {}█", self.filter)
+        } else {
+            format!(
+                "q quit | tab switch pane | / filter: {} | f follow: {}",
+                if self.filter.is_empty() {
+                    "none"
+                } else {
+                    &self.filter
+                },
+                if self.follow { "on" } else { "off" }
+            )
+        };
+        frame.render_widget(
+            Paragraph::new(text).style(Style::default().add_modifier(Modifier::REVERSED)),
+            area,
+        );
+    }
+}
+
+fn block(title: &str, focused: bool) -> Block<'_> {
+    let style = if focused {
+        Style::default().fg(Color::Green)
+    } else {
+        Style::default()
+    };
+    Block::default()
+        .borders(Borders::ALL)
+        .border_style(style)
+        .title(title)
+}
+
+fn hex_dump(buf: &[u8]) -> String {
+    buf.chunks(16)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                .collect();
+            format!("{:04x}  {:<47}  {}", line * 16, hex.join(" "), ascii)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Terminal input is blocking so it's read on its own thread
+fn spawn_input_reader() -> mpsc::UnboundedReceiver<KeyEvent> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || loop {
+        match event::poll(TICK) {
+            Ok(true) => {
+                if let Ok(Event::Key(key)) = event::read() {
+                    if tx.send(key).is_err() {
+                        break;
+                    }
+                }
+            }
+            Ok(false) => {
+                if tx.is_closed() {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    });
+    rx
+}
+
+fn restore(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> std::io::Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()
+}
+
+// A panic anywhere would otherwise print its message into the alternate screen and leave the
+// terminal in raw mode. Panics caught while decoding leave the terminal alone
+fn restore_on_panic() {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !decode::reading() {
+            let _ = disable_raw_mode();
+            let _ = execute!(stdout(), LeaveAlternateScreen, Show);
+        }
+        hook(info);
+    }));
+}
+
+pub async fn run(mut rx: Receiver<WSMessage>) -> std::io::Result<()> {
+    restore_on_panic();
+    enable_raw_mode()?;
+    let mut stdout = stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let mut keys = spawn_input_reader();
+    let mut app = App::new();
+    let mut tick = tokio::time::interval(TICK);
+
+    while !app.quit {
+        if let Err(e) = terminal.draw(|frame| app.draw(frame)) {
+            restore(&mut terminal)?;
+            return Err(e);
+        }
+
+        tokio::select! {
+            message = rx.recv() => match message {
+                Ok(message) => app.handle_message(message),
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            },
+            Some(key) = keys.recv() => app.handle_key(key),
+            _ = tick.tick() => {}
+        }
+    }
+
+    restore(&mut terminal)
+}
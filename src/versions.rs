@@ -0,0 +1,92 @@
+use eo::{
+    data::{EOChar, Serializeable, StreamReader},
+    protocol::{client::init::Init, PacketAction, PacketFamily},
+};
+
+use crate::{serialize, settings::VersionMapping, PacketBuf, SETTINGS};
+
+// Clients on a version the server won't accept are let in by rewriting the version in their
+// Init_Init to one it does. Where the protocol differs between the two, the mapping's adapter
+// script translates the session's packets, see scripting::adapt
+
+pub fn mapping(client_version: &str) -> Option<&'static VersionMapping> {
+    SETTINGS
+        .versions
+        .mappings
+        .iter()
+        .find(|mapping| mapping.client == client_version)
+}
+
+pub fn adapters() -> impl Iterator<Item = &'static str> {
+    SETTINGS
+        .versions
+        .mappings
+        .iter()
+        .filter_map(|mapping| mapping.adapter.as_deref())
+}
+
+// major.minor.patch
+pub fn parse(version: &str) -> Option<[EOChar; 3]> {
+    let mut parts = version.split('.').map(|part| part.trim().parse().ok());
+    let version = [parts.next()??, parts.next()??, parts.next()??];
+    parts.next().is_none().then_some(version)
+}
+
+// The Init_Init packet with its version replaced, or None if version isn't major.minor.patch
+pub fn rewrite_init(packet: &[u8], version: &str) -> Option<PacketBuf> {
+    let version = parse(version)?;
+
+    let mut init = Init::new();
+    init.deserialize(&StreamReader::new(packet.get(2..)?));
+    init.version = version;
+
+    let mut buf = vec![PacketAction::Init.to_byte(), PacketFamily::Init.to_byte()];
+    buf.extend(serialize(&init));
+    Some(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(parse("0.0.28"), Some([0, 0, 28]));
+        assert_eq!(parse(" 1 . 2 . 3 "), Some([1, 2, 3]));
+        assert_eq!(parse("0.0"), None);
+        assert_eq!(parse("0.0.28.1"), None);
+        assert_eq!(parse("0.0.x"), None);
+        assert_eq!(parse("0..28"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn rewrites_init_version() {
+        let mut init = Init::new();
+        init.challenge = 123456;
+        init.version = [0, 0, 28];
+        init.hdid = "1234567890".to_string();
+
+        let mut packet = vec![PacketAction::Init.to_byte(), PacketFamily::Init.to_byte()];
+        packet.extend(serialize(&init));
+
+        let rewritten = rewrite_init(&packet, "0.0.29").unwrap();
+        assert_eq!(&rewritten[..2], &packet[..2]);
+        assert_eq!(rewritten.len(), packet.len());
+
+        let mut decoded = Init::new();
+        decoded.deserialize(&StreamReader::new(&rewritten[2..]));
+        assert_eq!(decoded.challenge, 123456);
+        assert_eq!(decoded.version, [0, 0, 29]);
+        assert_eq!(decoded.hdid, "1234567890");
+
+        // Rewriting back gives the original packet
+        assert_eq!(rewrite_init(&rewritten, "0.0.28").unwrap(), packet);
+    }
+
+    #[test]
+    fn refuses_bad_versions() {
+        let packet = vec![PacketAction::Init.to_byte(), PacketFamily::Init.to_byte()];
+        assert_eq!(rewrite_init(&packet, "0.29"), None);
+    }
+}
@@ -0,0 +1,65 @@
+use eo::data::decode_number;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{duplex, split, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio_tungstenite::{
+    accept_async,
+    tungstenite::{protocol::Message, Error},
+    WebSocketStream,
+};
+
+use crate::bus::Stream;
+
+const BRIDGE_BUFFER_SIZE: usize = 64 * 1024;
+
+// Game clients connecting over WebSocket send EO packets, length prefix included, in binary
+// frames. The frames are bridged onto an in-memory stream so the session can read it with
+// the same framing as a TCP client, and every packet sent back goes out as its own frame
+pub async fn accept<S: Stream + 'static>(socket: S) -> Result<DuplexStream, Error> {
+    let websocket = accept_async(socket).await?;
+    let (bus_side, bridge_side) = duplex(BRIDGE_BUFFER_SIZE);
+    tokio::spawn(bridge(websocket, bridge_side));
+    Ok(bus_side)
+}
+
+async fn bridge<S: Stream>(websocket: WebSocketStream<S>, stream: DuplexStream) {
+    let (mut ws_sender, mut ws_receiver) = websocket.split();
+    let (mut reader, mut writer) = split(stream);
+
+    let incoming = async {
+        while let Some(message) = ws_receiver.next().await {
+            match message {
+                Ok(Message::Binary(data)) => writer.write_all(&data).await?,
+                Ok(Message::Close(_)) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    debug!("WebSocket client error: {}", e);
+                    break;
+                }
+            }
+        }
+        writer.shutdown().await
+    };
+
+    let outgoing = async {
+        loop {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length).await?;
+
+            let mut packet = vec![0; 2 + decode_number(&length) as usize];
+            packet[..2].copy_from_slice(&length);
+            reader.read_exact(&mut packet[2..]).await?;
+
+            if let Err(e) = ws_sender.send(Message::binary(packet)).await {
+                debug!("WebSocket client error: {}", e);
+                break;
+            }
+        }
+        let _ = ws_sender.close().await;
+        Ok::<(), std::io::Error>(())
+    };
+
+    tokio::select! {
+        _ = incoming => {},
+        _ = outgoing => {},
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    fs, io,
+    net::TcpListener as StdTcpListener,
+    path::PathBuf,
+    process::Stdio,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use eo::{
+    data::{decode_number, encode_number, EOByte, Serializeable, StreamReader},
+    net::PacketProcessor,
+    protocol::{
+        server::init::{Init, InitData},
+        PacketAction, PacketFamily,
+    },
+};
+use eoproxy::mock::{self, MockConfig};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command},
+    sync::mpsc,
+    time::{sleep, timeout},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static NEXT_PROXY: AtomicU32 = AtomicU32::new(0);
+
+fn free_port() -> u16 {
+    StdTcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+// The proxy binary running in its own directory, with a Config.toml pointing at server_port
+struct Proxy {
+    _child: Child,
+    directory: PathBuf,
+    port: u16,
+}
+
+impl Proxy {
+    async fn start(server_port: u16) -> Self {
+        let directory = std::env::temp_dir().join(format!(
+            "eoproxy-test-{}-{}",
+            std::process::id(),
+            NEXT_PROXY.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&directory).unwrap();
+
+        let port = free_port();
+        let monitor_port = free_port();
+        fs::write(
+            directory.join("Config.toml"),
+            format!(
+                "[server]\nhost = \"127.0.0.1\"\nport = \"{}\"\n\n\
+                 [proxy]\nhost = \"127.0.0.1\"\nport = \"{}\"\nmonitor_port = \"{}\"\n\n\
+                 [log]\nlevel = \"warn\"\n",
+                server_port, port, monitor_port
+            ),
+        )
+        .unwrap();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_eoproxy"))
+            .current_dir(&directory)
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+
+        let proxy = Self {
+            _child: child,
+            directory,
+            port,
+        };
+
+        // The monitor is bound after the game port, and connecting to it doesn't start a session
+        timeout(TIMEOUT, async {
+            while TcpStream::connect(("127.0.0.1", monitor_port))
+                .await
+                .is_err()
+            {
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("proxy didn't start listening");
+
+        proxy
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+// Serves connections with the mock server, reporting how each one ended
+async fn start_mock(config: MockConfig) -> (u16, mpsc::UnboundedReceiver<io::Result<()>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let config = config.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _ = tx.send(mock::handle(socket, &config).await);
+            });
+        }
+    });
+
+    (port, rx)
+}
+
+// A game client speaking the wire protocol directly, so tests control how bytes are written
+struct Client {
+    socket: TcpStream,
+    processor: PacketProcessor,
+}
+
+impl Client {
+    async fn connect(port: u16) -> Self {
+        Self {
+            socket: TcpStream::connect(("127.0.0.1", port)).await.unwrap(),
+            processor: PacketProcessor::new(),
+        }
+    }
+
+    fn frame(&mut self, action: EOByte, family: EOByte, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![action, family];
+        if family != PacketFamily::Init.to_byte() {
+            // Sequence number
+            buf.push(1);
+        }
+        buf.extend_from_slice(payload);
+        self.processor.encode(&mut buf);
+
+        let length = encode_number(buf.len() as u32);
+        buf.insert(0, length[1]);
+        buf.insert(0, length[0]);
+        buf
+    }
+
+    async fn send(&mut self, action: PacketAction, family: PacketFamily, payload: &[u8]) {
+        let frame = self.frame(action.to_byte(), family.to_byte(), payload);
+        self.socket.write_all(&frame).await.unwrap();
+    }
+
+    // None once the proxy closes the connection
+    async fn recv(&mut self) -> Option<Vec<u8>> {
+        let mut length = [0; 2];
+        timeout(TIMEOUT, self.socket.read_exact(&mut length))
+            .await
+            .expect("timed out waiting for a packet")
+            .ok()?;
+
+        let mut buf = vec![0; decode_number(&length) as usize];
+        timeout(TIMEOUT, self.socket.read_exact(&mut buf))
+            .await
+            .expect("timed out waiting for a packet")
+            .ok()?;
+        self.processor.decode(&mut buf);
+        Some(buf)
+    }
+
+    async fn handshake(&mut self) -> Init {
+        self.send(PacketAction::Init, PacketFamily::Init, &[1, 2, 3])
+            .await;
+        let packet = self.recv().await.expect("no init reply");
+        assert_eq!(packet[0], PacketAction::Init.to_byte());
+        assert_eq!(packet[1], PacketFamily::Init.to_byte());
+
+        let reader = StreamReader::new(&packet[2..]);
+        let mut reply = Init::new();
+        reply.deserialize(&reader);
+
+        if let InitData::Ok(ok) = &reply.data {
+            self.processor
+                .set_multiples(ok.encode_multiple, ok.decode_multiple);
+        }
+        reply
+    }
+
+    async fn expect_echo(&mut self, payload: &[u8]) {
+        let packet = self.recv().await.expect("no echo");
+        assert_eq!(packet[0], PacketAction::Player.to_byte());
+        assert_eq!(packet[1], PacketFamily::Walk.to_byte());
+        assert_eq!(&packet[2..], payload);
+    }
+}
+
+fn echo_config() -> MockConfig {
+    MockConfig {
+        encode_multiple: 7,
+        decode_multiple: 11,
+        player_id: 42,
+        echo: true,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn init_handshake_swaps_multiples() {
+    let (server_port, _) = start_mock(echo_config()).await;
+    let proxy = Proxy::start(server_port).await;
+    let mut client = Client::connect(proxy.port).await;
+
+    match client.handshake().await.data {
+        InitData::Ok(ok) => {
+            assert_eq!(ok.player_id, 42);
+            assert_eq!(ok.encode_multiple, 7);
+            assert_eq!(ok.decode_multiple, 11);
+        }
+        data => panic!("unexpected init reply {:?}", data),
+    }
+
+    // Only comes back intact if both sides of the proxy picked up the multiples
+    let payload = [3, 10, 20, 100, 200];
+    client
+        .send(PacketAction::Player, PacketFamily::Walk, &payload)
+        .await;
+    client.expect_echo(&payload).await;
+}
+
+#[tokio::test]
+async fn packets_split_across_reads_are_reassembled() {
+    let (server_port, _) = start_mock(echo_config()).await;
+    let proxy = Proxy::start(server_port).await;
+    let mut client = Client::connect(proxy.port).await;
+    client.handshake().await;
+
+    let payload = [1, 2, 3, 4, 5, 6, 7, 8];
+    let frame = client.frame(
+        PacketAction::Player.to_byte(),
+        PacketFamily::Walk.to_byte(),
+        &payload,
+    );
+    for byte in frame {
+        client.socket.write_all(&[byte]).await.unwrap();
+        client.socket.flush().await.unwrap();
+        sleep(Duration::from_millis(5)).await;
+    }
+    client.expect_echo(&payload).await;
+
+    // And the other way, two packets in one write
+    let mut frames = client.frame(
+        PacketAction::Player.to_byte(),
+        PacketFamily::Walk.to_byte(),
+        &payload[..4],
+    );
+    frames.extend(client.frame(
+        PacketAction::Player.to_byte(),
+        PacketFamily::Walk.to_byte(),
+        &payload[4..],
+    ));
+    client.socket.write_all(&frames).await.unwrap();
+    client.expect_echo(&payload[..4]).await;
+    client.expect_echo(&payload[4..]).await;
+}
+
+#[tokio::test]
+async fn large_packets_are_relayed() {
+    let (server_port, _) = start_mock(echo_config()).await;
+    let proxy = Proxy::start(server_port).await;
+    let mut client = Client::connect(proxy.port).await;
+    client.handshake().await;
+
+    let payload: Vec<u8> = (0..60000).map(|i| (i % 252) as u8 + 1).collect();
+    client
+        .send(PacketAction::Player, PacketFamily::Walk, &payload)
+        .await;
+    client.expect_echo(&payload).await;
+}
+
+#[tokio::test]
+async fn client_disconnect_closes_server_connection() {
+    let (server_port, mut closed) = start_mock(echo_config()).await;
+    let proxy = Proxy::start(server_port).await;
+    let mut client = Client::connect(proxy.port).await;
+    client.handshake().await;
+
+    drop(client);
+
+    let result = timeout(TIMEOUT, closed.recv())
+        .await
+        .expect("server connection was left open");
+    assert!(result.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn server_disconnect_closes_client_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 16];
+        let _ = socket.read(&mut buf).await;
+    });
+
+    let proxy = Proxy::start(server_port).await;
+    let mut client = Client::connect(proxy.port).await;
+    client
+        .send(PacketAction::Init, PacketFamily::Init, &[1, 2, 3])
+        .await;
+
+    assert_eq!(client.recv().await, None);
+}
+
+#[tokio::test]
+async fn unknown_family_and_action_are_relayed() {
+    let (server_port, _) = start_mock(echo_config()).await;
+    let proxy = Proxy::start(server_port).await;
+    let mut client = Client::connect(proxy.port).await;
+    client.handshake().await;
+
+    let unknown_action = (0..=255u8)
+        .find(|&b| PacketAction::from_byte(b).is_none())
+        .unwrap();
+    let unknown_family = (0..=255u8)
+        .find(|&b| PacketFamily::from_byte(b).is_none())
+        .unwrap();
+
+    let frame = client.frame(unknown_action, PacketFamily::Walk.to_byte(), &[1, 2]);
+    client.socket.write_all(&frame).await.unwrap();
+    let frame = client.frame(PacketAction::Player.to_byte(), unknown_family, &[1, 2]);
+    client.socket.write_all(&frame).await.unwrap();
+
+    // The session is still going
+    let payload = [9, 8, 7];
+    client
+        .send(PacketAction::Player, PacketFamily::Walk, &payload)
+        .await;
+    client.expect_echo(&payload).await;
+}
@@ -3,11 +3,17 @@ import useWebSocket, { ReadyState } from 'react-use-websocket';
 
 const ProxyContext = createContext({});
 
+// Set REACT_APP_MONITOR_TOKEN to the proxy's monitor.token
+const token = process.env.REACT_APP_MONITOR_TOKEN;
+const monitorUrl = token
+  ? `ws://localhost:9001/?token=${encodeURIComponent(token)}`
+  : 'ws://localhost:9001';
+
 // eslint-disable-next-line react/prop-types
 export default function ProxyProvider({ children }) {
   const [packets, setPackets] = useState([]);
-  const [players, setPlayers] = useState([]);
-  const { lastMessage, readyState } = useWebSocket('ws://localhost:9001');
+  const [sessions, setSessions] = useState([]);
+  const { lastMessage, readyState } = useWebSocket(monitorUrl);
 
   useEffect(() => {
     if (lastMessage !== null) {
@@ -15,23 +21,22 @@ export default function ProxyProvider({ children }) {
 
       console.log(lastMessage.data);
 
-      if (command === 'AddPlayer' && !players.includes(0)) {
-        setPlayers((prev) => prev.concat(0));
+      const { AddPlayer, Packet, SetPlayerId, RemovePlayer } = command;
+
+      if (AddPlayer) {
+        setSessions((prev) =>
+          prev.concat({ session_id: AddPlayer.session_id, player_id: 0 })
+        );
         return;
       }
 
-      const { Packet, SetPlayerId, RemovePlayer } = command;
-
       if (SetPlayerId) {
-        setPlayers((prev) => {
-          prev[prev.indexOf(0)] = SetPlayerId;
-          return prev;
-        });
+        const { session_id, player_id } = SetPlayerId;
+        setSessions((prev) =>
+          prev.map((s) => (s.session_id === session_id ? { ...s, player_id } : s))
+        );
         setPackets((prev) =>
-          prev.map((p) => ({
-            ...p,
-            player_id: p.player_id === 0 ? SetPlayerId : p.player_id
-          }))
+          prev.map((p) => (p.session_id === session_id ? { ...p, player_id } : p))
         );
         return;
       }
@@ -42,11 +47,15 @@ export default function ProxyProvider({ children }) {
       }
 
       if (RemovePlayer) {
-        setPlayers((prev) => prev.filter((p) => p !== RemovePlayer));
+        setSessions((prev) =>
+          prev.filter((s) => s.session_id !== RemovePlayer.session_id)
+        );
         return;
       }
     }
-  }, [lastMessage, setPackets, setPlayers]);
+  }, [lastMessage, setPackets, setSessions]);
+
+  const players = useMemo(() => sessions.map((s) => s.player_id), [sessions]);
 
   const connectionStatus = useMemo(
     () =>